@@ -4,13 +4,16 @@
   data.map(row => row.join(delimiter)).join("\n")
 }
 
-#let tokenize(input-text, user-dict: none, dict: "ipadic") = {
-  if dict not in ("ipadic", "unidic") {
-    panic("dict must be one of: ipadic, unidic")
+// Converts named Typst arguments (kebab-case) into plugin option keys (snake_case).
+#let plugin-options(options) = {
+  let out = (:)
+  for (key, value) in options.named() {
+    out.insert(key.replace("-", "_"), value)
   }
+  out
+}
 
-  let plugin = plugin(dict.replace("-", "_") + ".wasm")
-
+#let analysis-params(input-text, user-dict, options) = {
   let user-dict-csv = if user-dict != none {
     if type(user-dict) == str {
       user-dict
@@ -28,6 +31,20 @@
   } else {
     (text: input-text)
   }
+  params + plugin-options(options)
+}
+
+// Loads the WebAssembly plugin for `dict` after checking that it is supported.
+#let load-plugin(dict) = {
+  if dict not in ("ipadic", "unidic") {
+    panic("dict must be one of: ipadic, unidic")
+  }
+  plugin(dict.replace("-", "_") + ".wasm")
+}
+
+#let tokenize(input-text, user-dict: none, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params(input-text, user-dict, options)
   
   let result-bytes = plugin.analyze(bytes(json.encode(params)))
   let result-str = str(result-bytes)
@@ -35,8 +52,106 @@
   json(result-bytes)
 }
 
-#let show-analysis-table(input-text, user-dict: none, dict: "ipadic") = {
-  let tokens = tokenize(input-text, user-dict: user-dict, dict: dict)
+#let mecab-output(input-text, user-dict: none, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params(input-text, user-dict, options) + (output: "mecab")
+
+  let result-str = str(plugin.analyze(bytes(json.encode(params))))
+  if result-str.starts-with("Error:") { panic(result-str) }
+  result-str
+}
+
+#let aozora-output(input-text, user-dict: none, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params(input-text, user-dict, options) + (output: "aozora")
+
+  let result-str = str(plugin.analyze(bytes(json.encode(params))))
+  if result-str.starts-with("Error:") { panic(result-str) }
+  result-str
+}
+
+#let compact-ruby(input-text, user-dict: none, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params(input-text, user-dict, options) + (output: "compact")
+
+  let data = plugin.analyze(bytes(json.encode(params)))
+  if data.len() >= 6 and data.slice(0, 6) == bytes("Error:") { panic(str(data)) }
+
+  let read-u32(at) = int.from-bytes(data.slice(at, at + 4), endian: "little", signed: false)
+  let records = ()
+  let pos = 4
+  for _ in range(read-u32(0)) {
+    let base-len = read-u32(pos)
+    let base = str(data.slice(pos + 4, pos + 4 + base-len))
+    pos += 4 + base-len
+    let rt-len = read-u32(pos)
+    let rt = str(data.slice(pos + 4, pos + 4 + rt-len))
+    pos += 4 + rt-len
+    records.push((base: base, rt: rt))
+  }
+  records
+}
+
+#let reading-coverage(input-text, user-dict: none, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params(input-text, user-dict, options) + (coverage: true)
+
+  let result-bytes = plugin.analyze(bytes(json.encode(params)))
+  if str(result-bytes).starts-with("Error:") { panic(str(result-bytes)) }
+  json(result-bytes)
+}
+
+#let reading-only(input-text, user-dict: none, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params(input-text, user-dict, options)
+
+  let result-str = str(plugin.reading_only(bytes(json.encode(params))))
+  if result-str.starts-with("Error:") { panic(result-str) }
+  result-str
+}
+
+#let readings-for-words(words, user-dict: none, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params("", user-dict, options) + (words: words)
+
+  let result-bytes = plugin.readings_for_words(bytes(json.encode(params)))
+  let result-str = str(result-bytes)
+  if result-str.starts-with("Error:") { panic(result-str) }
+  json(result-bytes)
+}
+
+#let ruby-for-spans(spans, dict: "ipadic", ..options) = {
+  let plugin = load-plugin(dict)
+  let params = analysis-params("", none, options) + (spans: spans)
+
+  let result-bytes = plugin.build_ruby_for_spans(bytes(json.encode(params)))
+  let result-str = str(result-bytes)
+  if result-str.starts-with("Error:") { panic(result-str) }
+  json(result-bytes)
+}
+
+#let strip-ruby(input-text, styles: ("paren", "aozora"), dict: "ipadic") = {
+  let plugin = load-plugin(dict)
+  let result-str = str(plugin.strip_ruby(bytes(json.encode((text: input-text, styles: styles)))))
+  if result-str.starts-with("Error:") { panic(result-str) }
+  result-str
+}
+
+#let surfaces-for-reading(reading, dict: "ipadic") = {
+  let plugin = load-plugin(dict)
+  let result-bytes = plugin.surfaces_for_reading(bytes(json.encode((reading: reading))))
+  let result-str = str(result-bytes)
+  if result-str.starts-with("Error:") { panic(result-str) }
+  json(result-bytes)
+}
+
+#let detail-schema(dict: "ipadic") = {
+  let plugin = load-plugin(dict)
+  json(plugin.detail_schema())
+}
+
+#let show-analysis-table(input-text, user-dict: none, dict: "ipadic", ..options) = {
+  let tokens = tokenize(input-text, user-dict: user-dict, dict: dict, ..options)
   let get-safe(arr, idx) = {
     if idx < arr.len() { arr.at(idx) } else { "*" }
   }
@@ -113,8 +228,8 @@
   }
 }
 
-#let show-ruby(input-text, size: 0.5em, leading: 1.5em, ruby-func: auto, user-dict: none, dict: "ipadic") = {
-  let tokens = tokenize(input-text, user-dict: user-dict, dict: dict)
+#let show-ruby(input-text, size: 0.5em, leading: 1.5em, ruby-func: auto, user-dict: none, dict: "ipadic", ..options) = {
+  let tokens = tokenize(input-text, user-dict: user-dict, dict: dict, ..options)
   
   let cmd = if ruby-func == auto {
     get-ruby(size: size)
@@ -0,0 +1,1399 @@
+//! Reading alignment: splits a token's reading over its surface into ruby
+//! segments, with the reading heuristics (numbers, counters, dates, context)
+//! that may replace a dictionary reading first.
+
+use super::*;
+
+/// One ruby unit: base `text` and the `ruby` over it (empty for bare text).
+#[derive(Serialize, Default, Clone)]
+pub(crate) struct RubySegment {
+    pub(crate) text: String,
+    pub(crate) ruby: String,
+    /// Katakana reading before `output_kana` conversion (`include_raw_reading`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) raw_ruby: Option<String>,
+    /// On okurigana, which neighbouring kanji it belongs to: "prev", or "next"
+    /// for leading kana such as the お of お菓子.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) attaches_to: Option<&'static str>,
+    /// On/kun classification of a kanji segment's ruby (`reading_type`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) reading_type: Option<&'static str>,
+    /// Part of speech of the parent token (`inherit_pos`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) pos: Option<String>,
+    /// Morae of a group ruby allotted to each base character
+    /// (`distribute_group_ruby`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) distribution: Option<Vec<String>>,
+}
+
+/// Confidence attached to readings chosen by a heuristic table.
+pub(crate) const MEDIUM_CONFIDENCE: f32 = 0.5;
+
+/// Confidence of a dictionary reading whose alignment fell back.
+pub(crate) const LOW_CONFIDENCE: f32 = 0.25;
+
+/// Whether a kana-only surface should still receive ruby under the annotate_* flags.
+pub(crate) fn annotates_kana(surface: &str, params: &InputParams) -> bool {
+    if surface.is_empty() {
+        return false;
+    }
+    (params.annotate_katakana && surface.chars().all(is_katakana))
+        || (params.annotate_hiragana && surface.chars().all(|c| is_hiragana(c) || c == 'ー'))
+}
+
+/// Common reading of an administrative suffix following a place name.
+/// 町 (ちょう/まち) and 村 (そん/むら) vary by municipality, so the more
+/// widespread reading is chosen and the result is only medium-confidence.
+pub(crate) fn place_suffix_reading(surface: &str) -> Option<&'static str> {
+    match surface {
+        "郡" => Some("グン"),
+        "市" => Some("シ"),
+        "町" => Some("チョウ"),
+        "村" => Some("ムラ"),
+        _ => None,
+    }
+}
+
+/// Applies rendaku to the first kana of a katakana reading (ヒト -> ビト).
+pub(crate) fn voice_first_kana(reading: &str) -> String {
+    let mut chars = reading.chars();
+    let Some(first) = chars.next() else {
+        return String::new();
+    };
+    let voiced = match first {
+        'カ' | 'キ' | 'ク' | 'ケ' | 'コ' | 'サ' | 'シ' | 'ス' | 'セ' | 'ソ' | 'タ' | 'チ' | 'ツ' | 'テ' | 'ト' => {
+            std::char::from_u32(first as u32 + 1).unwrap()
+        }
+        'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' => std::char::from_u32(first as u32 + 1).unwrap(),
+        _ => first,
+    };
+    std::iter::once(voiced).chain(chars).collect()
+}
+
+/// Reading of a single kanji that ends a token, used to resolve a 々 starting the next token.
+pub(crate) fn trailing_kanji_reading(segments: &[RubySegment]) -> Option<String> {
+    let last = segments.last()?;
+    let mut chars = last.text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if is_kanji(c) && !last.ruby.is_empty() => Some(last.ruby.clone()),
+        _ => None,
+    }
+}
+
+/// Segments for a token that starts with 々 because the tokenizer split it from its kanji.
+/// The mark repeats the previous kanji's reading with rendaku (人|々 -> ひと|びと).
+pub(crate) fn iteration_mark_segments(surface: &str, reading: &str, prev_reading: &str, params: &InputParams) -> Vec<RubySegment> {
+    let mark_reading = voice_first_kana(prev_reading);
+    let rest = &surface['々'.len_utf8()..];
+
+    let mut segments = vec![RubySegment {
+        text: "々".to_string(),
+        ruby: mark_reading.clone(),
+        ..Default::default()
+    }];
+    if !rest.is_empty() {
+        let rest_reading = reading.strip_prefix(mark_reading.as_str()).unwrap_or("*");
+        segments.extend(build_ruby_segments(rest, rest_reading, params));
+    }
+    segments
+}
+
+/// Parses a token made only of ASCII or full-width digits.
+pub(crate) fn parse_number(surface: &str) -> Option<u32> {
+    if surface.is_empty() {
+        return None;
+    }
+    let mut value: u32 = 0;
+    for c in surface.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            '０'..='９' => c as u32 - '０' as u32,
+            _ => return None,
+        };
+        value = value.checked_mul(10)?.checked_add(digit)?;
+    }
+    Some(value)
+}
+
+/// Value of a kanji numeral below 100 (七 -> 7, 十 -> 10, 二十四 -> 24).
+pub(crate) fn parse_kanji_number(surface: &str) -> Option<u32> {
+    let digit = |s: &str| {
+        let mut chars = s.chars();
+        let c = chars.next().filter(|_| chars.next().is_none())?;
+        "〇一二三四五六七八九".chars().position(|d| d == c).map(|d| d as u32)
+    };
+    match surface.split_once('十') {
+        Some((tens, ones)) => {
+            let tens = if tens.is_empty() { 1 } else { digit(tens)? };
+            let ones = if ones.is_empty() { 0 } else { digit(ones)? };
+            Some(tens * 10 + ones)
+        }
+        None => digit(surface),
+    }
+}
+
+/// Two-segment ruby for a token that is a kanji number + date/time unit
+/// whose number is read irregularly (一日 -> ツイ+タチ, 二十日 -> ハツ+カ).
+pub(crate) fn datetime_segments(surface: &str) -> Option<Vec<RubySegment>> {
+    let (unit_at, _) = surface.char_indices().last()?;
+    let (number, unit) = surface.split_at(unit_at);
+    let (number_ruby, unit_ruby) = datetime_reading(parse_kanji_number(number)?, unit)?;
+    Some(vec![
+        RubySegment { text: number.to_string(), ruby: number_ruby?.to_string(), ..Default::default() },
+        RubySegment { text: unit.to_string(), ruby: unit_ruby.to_string(), ..Default::default() },
+    ])
+}
+
+/// Readings for a date/time unit following a number, as
+/// `(number ruby, unit ruby)`. The number only gets ruby when its
+/// pronunciation changes (4月 -> し, 2日 -> ふつ, 8分 -> はっ); for
+/// multi-digit numbers not in the table only the unit reading is adjusted.
+pub(crate) fn datetime_reading(number: u32, unit: &str) -> Option<(Option<&'static str>, &'static str)> {
+    let reading = match unit {
+        "月" => match number {
+            4 => (Some("シ"), "ガツ"),
+            7 => (Some("シチ"), "ガツ"),
+            9 => (Some("ク"), "ガツ"),
+            _ => (None, "ガツ"),
+        },
+        "日" => match number {
+            1 => (Some("ツイ"), "タチ"),
+            2 => (Some("フツ"), "カ"),
+            3 => (Some("ミッ"), "カ"),
+            4 => (Some("ヨッ"), "カ"),
+            5 => (Some("イツ"), "カ"),
+            6 => (Some("ムイ"), "カ"),
+            7 => (Some("ナノ"), "カ"),
+            8 => (Some("ヨウ"), "カ"),
+            9 => (Some("ココノ"), "カ"),
+            10 => (Some("トオ"), "カ"),
+            14 => (Some("ジュウヨッ"), "カ"),
+            20 => (Some("ハツ"), "カ"),
+            24 => (Some("ニジュウヨッ"), "カ"),
+            _ => (None, "ニチ"),
+        },
+        "時" => match number {
+            4 => (Some("ヨ"), "ジ"),
+            7 => (Some("シチ"), "ジ"),
+            9 => (Some("ク"), "ジ"),
+            _ => (None, "ジ"),
+        },
+        "分" => match number {
+            1 => (Some("イッ"), "プン"),
+            6 => (Some("ロッ"), "プン"),
+            8 => (Some("ハッ"), "プン"),
+            10 => (Some("ジュッ"), "プン"),
+            _ => match number % 10 {
+                0 | 1 | 3 | 4 | 6 | 8 => (None, "プン"),
+                _ => (None, "フン"),
+            },
+        },
+        _ => return None,
+    };
+    Some(reading)
+}
+
+/// Counters with sound changes after a single-kanji numeral, as
+/// `(counter, plain, after 三, after a geminated 一/六/八/十)`.
+pub(crate) const COUNTERS: &[(&str, &str, &str, &str)] = &[
+    ("匹", "ヒキ", "ビキ", "ピキ"),
+    ("本", "ホン", "ボン", "ポン"),
+    ("杯", "ハイ", "バイ", "パイ"),
+    ("百", "ヒャク", "ビャク", "ピャク"),
+    ("分", "フン", "プン", "プン"),
+    ("発", "ハツ", "パツ", "パツ"),
+    ("千", "セン", "ゼン", "セン"),
+    ("個", "コ", "コ", "コ"),
+    ("回", "カイ", "カイ", "カイ"),
+    ("階", "カイ", "ガイ", "カイ"),
+    ("軒", "ケン", "ゲン", "ケン"),
+    ("冊", "サツ", "サツ", "サツ"),
+    ("足", "ソク", "ゾク", "ソク"),
+    ("歳", "サイ", "サイ", "サイ"),
+];
+
+/// Readings for a kanji numeral followed by a counter, as
+/// `(numeral ruby, counter ruby)`. 六 only geminates before h/k-row
+/// counters (六冊 -> ろくさつ).
+pub(crate) fn counter_reading(numeral: char, counter: &str) -> Option<(&'static str, &'static str)> {
+    let &(_, plain, after_three, after_geminate) = COUNTERS.iter().find(|(c, ..)| *c == counter)?;
+    let s_row = matches!(plain.chars().next(), Some('サ' | 'セ' | 'ソ'));
+    let (numeral_ruby, geminated) = match numeral {
+        '一' => ("イッ", true),
+        '二' => ("ニ", false),
+        '三' => ("サン", false),
+        '四' => ("ヨン", false),
+        '五' => ("ゴ", false),
+        '六' if s_row => ("ロク", false),
+        '六' => ("ロッ", true),
+        '七' => ("ナナ", false),
+        '八' => ("ハッ", true),
+        '九' => ("キュウ", false),
+        '十' => ("ジュッ", true),
+        _ => return None,
+    };
+    let counter_ruby = if geminated {
+        after_geminate
+    } else if numeral == '三' || (numeral == '四' && counter == "分") {
+        after_three
+    } else {
+        plain
+    };
+    Some((numeral_ruby, counter_ruby))
+}
+
+/// Two-segment ruby for a token that is a numeral + counter (三匹).
+pub(crate) fn counter_segments(surface: &str) -> Option<Vec<RubySegment>> {
+    let mut chars = surface.chars();
+    let numeral = chars.next()?;
+    let counter = chars.as_str();
+    let (numeral_ruby, counter_ruby) = counter_reading(numeral, counter)?;
+    Some(vec![
+        RubySegment { text: numeral.to_string(), ruby: numeral_ruby.to_string(), ..Default::default() },
+        RubySegment { text: counter.to_string(), ruby: counter_ruby.to_string(), ..Default::default() },
+    ])
+}
+
+/// The numeral of a token consisting of one kanji numeral.
+pub(crate) fn single_numeral(surface: &str) -> Option<char> {
+    let mut chars = surface.chars();
+    chars.next().filter(|&c| chars.next().is_none() && is_kanji_numeral(c))
+}
+
+pub(crate) fn is_kanji_numeral(c: char) -> bool {
+    "〇一二三四五六七八九十百千".contains(c)
+}
+
+pub(crate) fn kanji_numeral_readings(c: char) -> &'static [&'static str] {
+    match c {
+        '〇' => &["ゼロ", "レイ"],
+        '一' => &["イチ"],
+        '二' => &["ニ"],
+        '三' => &["サン"],
+        '四' => &["ヨン", "ヨ", "シ"],
+        '五' => &["ゴ"],
+        '六' => &["ロク"],
+        '七' => &["ナナ", "シチ"],
+        '八' => &["ハチ"],
+        '九' => &["キュウ", "ク"],
+        '十' => &["ジュウ"],
+        '百' => &["ヒャク", "ビャク", "ピャク"],
+        '千' => &["セン", "ゼン"],
+        _ => &[],
+    }
+}
+
+/// All plausible readings of a kanji numeral, including the geminated forms
+/// used before counters (一 -> イッ, 六 -> ロッ, 十 -> ジュッ/ジッ).
+pub(crate) fn numeral_reading_candidates(numeral: &str) -> Vec<String> {
+    let mut candidates = vec![String::new()];
+    for c in numeral.chars() {
+        let readings = kanji_numeral_readings(c);
+        candidates = candidates
+            .iter()
+            .flat_map(|prefix| readings.iter().map(move |r| format!("{}{}", prefix, r)))
+            .collect();
+    }
+
+    let mut geminated = Vec::new();
+    for candidate in &candidates {
+        if let Some(stem) = candidate.strip_suffix("ジュウ") {
+            geminated.push(format!("{}ジュッ", stem));
+            geminated.push(format!("{}ジッ", stem));
+        } else if let Some(stem) = candidate.strip_suffix(['チ', 'ク', 'ツ']) {
+            geminated.push(format!("{}ッ", stem));
+        }
+    }
+    candidates.extend(geminated);
+    candidates
+}
+
+pub(crate) fn is_suppressed(byte_start: usize, params: &InputParams) -> bool {
+    params.suppress_ranges.as_ref().is_some_and(|ranges| {
+        ranges.iter().any(|&(start, end)| byte_start >= start && byte_start < end)
+    })
+}
+
+/// `token_ruby_segments` memoized for one analysis call. All options are fixed
+/// for the call, so `(surface, reading)` is a complete key; suppressed tokens
+/// depend on their offset and bypass the cache.
+pub(crate) fn cached_ruby_segments(
+    cache: &mut HashMap<(String, String), Vec<RubySegment>>,
+    surface: &str,
+    reading: &str,
+    byte_start: usize,
+    params: &InputParams,
+) -> Vec<RubySegment> {
+    if is_suppressed(byte_start, params) {
+        return token_ruby_segments(surface, reading, byte_start, params);
+    }
+    cache
+        .entry((surface.to_string(), reading.to_string()))
+        .or_insert_with(|| token_ruby_segments(surface, reading, byte_start, params))
+        .clone()
+}
+
+/// Produces the ruby segments for one token: option-driven overrides first,
+/// then the regular reading alignment.
+pub(crate) fn token_ruby_segments(surface: &str, reading: &str, byte_start: usize, params: &InputParams) -> Vec<RubySegment> {
+    if is_suppressed(byte_start, params) {
+        return vec![RubySegment {
+            text: surface.to_string(),
+            ruby: "".to_string(),
+            ..Default::default()
+        }];
+    }
+
+    if let Some(symbol_reading) = symbol_reading(surface).filter(|_| params.symbol_readings) {
+        return vec![RubySegment {
+            text: surface.to_string(),
+            ruby: symbol_reading.to_string(),
+            ..Default::default()
+        }];
+    }
+
+    if is_symbol_token(surface) {
+        return vec![RubySegment {
+            text: surface.to_string(),
+            ruby: "".to_string(),
+            ..Default::default()
+        }];
+    }
+
+    if params.latin_to_kana {
+        if let Some(latin_reading) = spell_latin(surface, params.latin_digits_to_kana) {
+            return vec![RubySegment {
+                text: surface.to_string(),
+                ruby: latin_reading,
+                ..Default::default()
+            }];
+        }
+    }
+
+    let mut segments = match jukujikun_reading(surface, params) {
+        Some(table_reading) => vec![RubySegment {
+            text: surface.to_string(),
+            ruby: if reading == "*" { table_reading } else { reading.to_string() },
+            ..Default::default()
+        }],
+        None => match honorific_prefix(surface, reading).filter(|_| params.honorific_prefix_bare) {
+            Some((prefix, rest, rest_reading)) => {
+                let mut segments = vec![RubySegment {
+                    text: prefix.to_string(),
+                    ruby: "".to_string(),
+                    ..Default::default()
+                }];
+                segments.extend(build_ruby_segments(rest, rest_reading, params));
+                segments
+            }
+            None => build_ruby_segments(surface, reading, params),
+        },
+    };
+    if let Some(points) = params.split_points.as_ref().and_then(|map| map.get(surface)) {
+        segments = apply_split_points(segments, points);
+    }
+    if params.ruby_mode == "mono" && jukujikun_reading(surface, params).is_none() {
+        segments = mono_ruby_segments(segments);
+    }
+    if let Some(ranges) = &params.ruby_kanji_ranges {
+        let in_ranges = |c: char| ranges.iter().any(|&(start, end)| (start..=end).contains(&(c as u32)));
+        for seg in segments.iter_mut() {
+            if !seg.text.chars().any(in_ranges) {
+                seg.ruby.clear();
+            }
+        }
+    }
+    if let Some(targets) = &params.target_kanji {
+        segments = target_kanji_segments(segments, targets);
+    }
+    if params.lead_kanji_only {
+        segments = lead_kanji_only(segments);
+    }
+    if params.tag_okurigana {
+        tag_okurigana(&mut segments);
+    }
+    if params.reading_type {
+        for seg in segments.iter_mut().filter(|seg| !seg.ruby.is_empty() && seg.text.chars().any(is_kanji)) {
+            seg.reading_type = Some(segment_reading_type(&seg.text, &seg.ruby));
+        }
+    }
+    if params.collapse_empty_segments {
+        segments = collapse_empty_segments(segments);
+    }
+    segments
+}
+
+/// Merges runs of adjacent empty-ruby segments; segments with ruby stay as boundaries.
+pub(crate) fn collapse_empty_segments(segments: Vec<RubySegment>) -> Vec<RubySegment> {
+    let mut out: Vec<RubySegment> = Vec::with_capacity(segments.len());
+    for seg in segments {
+        match out.last_mut() {
+            Some(last) if last.ruby.is_empty() && seg.ruby.is_empty() && last.attaches_to == seg.attaches_to => {
+                last.text.push_str(&seg.text);
+            }
+            _ => out.push(seg),
+        }
+    }
+    out
+}
+
+/// Sets `attaches_to` on the hiragana segments of a word containing kanji.
+/// Okurigana follows its kanji, so it attaches to the previous one unless no
+/// kanji precedes it (a leading prefix like お), in which case it is "next".
+pub(crate) fn tag_okurigana(segments: &mut [RubySegment]) {
+    let has_kanji: Vec<bool> = segments.iter().map(|seg| seg.text.chars().any(is_kanji)).collect();
+    if !has_kanji.contains(&true) {
+        return;
+    }
+    for (i, seg) in segments.iter_mut().enumerate() {
+        if has_kanji[i] || !seg.text.chars().all(is_hiragana) {
+            continue;
+        }
+        seg.attaches_to = Some(if has_kanji[..i].contains(&true) { "prev" } else { "next" });
+    }
+}
+
+/// Puts the lengthening marks dropped by `tolerate_lengthening` back into
+/// segments aligned without them: `marks` are char indices in `chars`. A
+/// mark inside a segment joins its text; one between segments is bare.
+pub(crate) fn reinsert_lengthening(segments: Vec<RubySegment>, chars: &[char], marks: &[usize]) -> Vec<RubySegment> {
+    let bare = |c: char| RubySegment {
+        text: c.to_string(),
+        ruby: "".to_string(),
+        ..Default::default()
+    };
+    let mut out = Vec::with_capacity(segments.len() + marks.len());
+    let mut pending = segments.into_iter();
+    let mut current: Option<(RubySegment, usize)> = None;
+    for (i, &c) in chars.iter().enumerate() {
+        if marks.contains(&i) {
+            match current.as_mut() {
+                Some((seg, _)) => seg.text.push(c),
+                None => out.push(bare(c)),
+            }
+            continue;
+        }
+        if current.is_none() {
+            current = pending.next().map(|seg| {
+                let len = seg.text.chars().count();
+                (RubySegment { text: String::new(), ..seg }, len)
+            });
+        }
+        let Some((seg, left)) = current.as_mut() else {
+            out.push(bare(c));
+            continue;
+        };
+        seg.text.push(c);
+        *left = left.saturating_sub(1);
+        if *left == 0 {
+            out.extend(current.take().map(|(seg, _)| seg));
+        }
+    }
+    out.extend(current.map(|(seg, _)| seg));
+    out
+}
+
+/// Splits a leading honorific お/ご off a kanji word whose reading starts
+/// with the same kana: お茶/オチャ -> ("お", "茶", "チャ").
+pub(crate) fn honorific_prefix<'a>(surface: &'a str, reading: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
+    let prefix = ["お", "ご"].into_iter().find(|p| surface.starts_with(p))?;
+    let rest = &surface[prefix.len()..];
+    let rest_reading = reading.strip_prefix(&hira_to_kata(prefix.chars().next()?).to_string())?;
+    (rest.chars().any(is_kanji) && !rest_reading.is_empty()).then_some((prefix, rest, rest_reading))
+}
+
+/// Spoken reading of a standalone symbol token (`symbol_readings`).
+pub(crate) fn symbol_reading(surface: &str) -> Option<&'static str> {
+    let reading = match surface {
+        "〒" => "ユウビン",
+        "℃" => "ド",
+        "※" => "コメ",
+        "〆" => "シメ",
+        "％" | "%" => "パーセント",
+        "￥" | "¥" => "エン",
+        "＄" | "$" => "ドル",
+        "㎏" => "キログラム",
+        "㎞" => "キロメートル",
+        "㎝" => "センチメートル",
+        "㎜" => "ミリメートル",
+        "㎡" => "ヘイホウメートル",
+        _ => return None,
+    };
+    Some(reading)
+}
+
+/// Homograph readings chosen by the particle right before the word:
+/// (surface, previous token, reading). を marks an object, so 行った is
+/// おこなった; に/へ mark a destination, so it is いった. Likewise 開く
+/// after が (the door opens) is あく, after を (hold a meeting) ひらく.
+pub(crate) const CONTEXT_RULES: &[(&str, &str, &str)] = &[
+    ("行っ", "を", "オコナッ"),
+    ("行っ", "に", "イッ"),
+    ("行っ", "へ", "イッ"),
+    ("行った", "を", "オコナッタ"),
+    ("行った", "に", "イッタ"),
+    ("行った", "へ", "イッタ"),
+    ("開く", "が", "アク"),
+    ("開く", "を", "ヒラク"),
+    ("開い", "が", "アイ"),
+    ("開い", "を", "ヒライ"),
+];
+
+pub(crate) fn context_reading(surface: &str, prev_surface: Option<&str>) -> Option<&'static str> {
+    let prev_surface = prev_surface?;
+    CONTEXT_RULES
+        .iter()
+        .find(|(word, prev, _)| *word == surface && *prev == prev_surface)
+        .map(|(_, _, reading)| *reading)
+}
+
+/// On'yomi and kun'yomi (katakana) of common kanji, used by `reading_type`,
+/// `target_kanji` and mono ruby to divide a reading among kanji.
+/// Kun readings list only the part covered by the kanji (食 -> タ of 食べる).
+pub(crate) const KANJI_READINGS: &[(char, &[&str], &[&str])] = &[
+    ('音', &["オン", "イン"], &["オト", "ネ"]),
+    ('日', &["ニチ", "ジツ"], &["ヒ", "カ"]),
+    ('月', &["ゲツ", "ガツ"], &["ツキ"]),
+    ('火', &["カ"], &["ヒ"]),
+    ('水', &["スイ"], &["ミズ"]),
+    ('木', &["モク", "ボク"], &["キ", "コ"]),
+    ('金', &["キン", "コン"], &["カネ", "カナ"]),
+    ('土', &["ド", "ト"], &["ツチ"]),
+    ('山', &["サン"], &["ヤマ"]),
+    ('川', &["セン"], &["カワ"]),
+    ('人', &["ジン", "ニン"], &["ヒト"]),
+    ('大', &["ダイ", "タイ"], &["オオ"]),
+    ('小', &["ショウ"], &["チイ", "コ", "オ"]),
+    ('中', &["チュウ"], &["ナカ"]),
+    ('上', &["ジョウ"], &["ウエ", "カミ", "ア", "ノボ"]),
+    ('下', &["カ", "ゲ"], &["シタ", "シモ", "モト", "サ", "クダ", "オ"]),
+    ('学', &["ガク"], &["マナ"]),
+    ('生', &["セイ", "ショウ"], &["イ", "ウ", "ナマ", "ハ", "キ"]),
+    ('先', &["セン"], &["サキ"]),
+    ('年', &["ネン"], &["トシ"]),
+    ('時', &["ジ"], &["トキ"]),
+    ('行', &["コウ", "ギョウ"], &["イ", "ユ", "オコナ"]),
+    ('見', &["ケン"], &["ミ"]),
+    ('食', &["ショク"], &["タ", "ク"]),
+    ('東', &["トウ"], &["ヒガシ"]),
+    ('京', &["キョウ", "ケイ"], &[]),
+    ('国', &["コク"], &["クニ"]),
+    ('語', &["ゴ"], &["カタ"]),
+    ('本', &["ホン"], &["モト"]),
+    ('書', &["ショ"], &["カ"]),
+    ('読', &["ドク"], &["ヨ"]),
+    ('話', &["ワ"], &["ハナシ", "ハナ"]),
+    ('言', &["ゲン", "ゴン"], &["イ", "コト"]),
+    ('手', &["シュ"], &["テ"]),
+    ('口', &["コウ", "ク"], &["クチ"]),
+    ('目', &["モク"], &["メ"]),
+    ('的', &["テキ"], &["マト"]),
+    ('花', &["カ"], &["ハナ"]),
+    ('私', &["シ"], &["ワタシ", "ワタクシ"]),
+    ('今', &["コン", "キン"], &["イマ"]),
+    ('明', &["メイ", "ミョウ"], &["アカ", "アキ"]),
+    ('新', &["シン"], &["アタラ", "アラ"]),
+    ('高', &["コウ"], &["タカ"]),
+    ('長', &["チョウ"], &["ナガ"]),
+    ('白', &["ハク"], &["シロ"]),
+    ('雨', &["ウ"], &["アメ"]),
+    ('電', &["デン"], &[]),
+    ('車', &["シャ"], &["クルマ"]),
+    ('会', &["カイ"], &["ア"]),
+    ('分', &["ブン", "フン", "ブ"], &["ワ"]),
+    ('入', &["ニュウ"], &["イ", "ハイ"]),
+    ('出', &["シュツ"], &["デ", "ダ"]),
+    ('子', &["シ", "ス"], &["コ"]),
+    ('字', &["ジ"], &["アザ"]),
+    ('漢', &["カン"], &[]),
+    ('茶', &["チャ", "サ"], &[]),
+    ('強', &["キョウ"], &["ツヨ"]),
+    ('勉', &["ベン"], &[]),
+    ('校', &["コウ"], &[]),
+    ('曜', &["ヨウ"], &[]),
+];
+
+/// Whether `reading` is `base`, allowing the sound changes of compounds:
+/// rendaku on the first kana (ハナ -> バナ) and gemination of a final
+/// ツ/チ/ク/キ (ガク -> ガッ).
+pub(crate) fn reading_variant_of(reading: &str, base: &str) -> bool {
+    if reading == base {
+        return true;
+    }
+    let r: Vec<char> = reading.chars().collect();
+    let b: Vec<char> = base.chars().collect();
+    if r.len() != b.len() || r.is_empty() {
+        return false;
+    }
+    let first_ok = r[0] == b[0]
+        || compose_halfwidth_voicing(b[0], '\u{3099}') == Some(r[0])
+        || compose_halfwidth_voicing(b[0], '\u{309A}') == Some(r[0]);
+    let last = r.len() - 1;
+    let last_ok = last == 0 || r[last] == b[last] || (r[last] == 'ッ' && matches!(b[last], 'ツ' | 'チ' | 'ク' | 'キ'));
+    first_ok && last_ok && r[1..last.max(1)] == b[1..last.max(1)]
+}
+
+/// Compounds with renjou (連声): the ン or ツ closing the first kanji turns
+/// the vowel-initial on'yomi of the next into a ナ/マ/タ-row one (観音 カン
+/// + オン -> カンノン), so the split cannot be found from `KANJI_READINGS`.
+pub(crate) const LIAISON_READINGS: &[(&str, &[&str])] = &[
+    ("観音", &["カン", "ノン"]),
+    ("天皇", &["テン", "ノウ"]),
+    ("反応", &["ハン", "ノウ"]),
+    ("順応", &["ジュン", "ノウ"]),
+    ("因縁", &["イン", "ネン"]),
+    ("陰陽", &["オン", "ミョウ"]),
+    ("三位", &["サン", "ミ"]),
+    ("雪隠", &["セッ", "チン"]),
+];
+
+/// Divides `reading` among the kanji of `kanji`, trying every split against
+/// `KANJI_READINGS`, and classifies each part as "on" or "kun"; None if no
+/// split matches.
+pub(crate) fn kanji_reading_parts<'a>(kanji: &[char], reading: &'a str) -> Option<Vec<(&'static str, &'a str)>> {
+    if let Some((_, parts)) = LIAISON_READINGS
+        .iter()
+        .find(|(word, parts)| word.chars().eq(kanji.iter().copied()) && parts.concat() == reading)
+    {
+        let mut rest = reading;
+        return Some(
+            parts
+                .iter()
+                .map(|part| {
+                    let (head, tail) = rest.split_at(part.len());
+                    rest = tail;
+                    ("on", head)
+                })
+                .collect(),
+        );
+    }
+    let (&first, rest) = kanji.split_first()?;
+    let (_, on, kun) = KANJI_READINGS.iter().find(|(c, _, _)| *c == first)?;
+    for (end, _) in reading.char_indices().skip(1).chain(std::iter::once((reading.len(), ' '))) {
+        let (head, tail) = reading.split_at(end);
+        let kind = if on.iter().any(|r| reading_variant_of(head, r)) {
+            "on"
+        } else if kun.iter().any(|r| reading_variant_of(head, r)) {
+            "kun"
+        } else {
+            continue;
+        };
+        if rest.is_empty() {
+            if tail.is_empty() {
+                return Some(vec![(kind, head)]);
+            }
+            continue;
+        }
+        if let Some(mut parts) = kanji_reading_parts(rest, tail) {
+            parts.insert(0, (kind, head));
+            return Some(parts);
+        }
+    }
+    None
+}
+
+/// "on", "kun", "mixed" (重箱/湯桶 readings) or "unknown" for a kanji segment.
+pub(crate) fn segment_reading_type(text: &str, ruby: &str) -> &'static str {
+    let kanji: Vec<char> = text.chars().collect();
+    if !kanji.iter().all(|&c| is_kanji(c)) {
+        return "unknown";
+    }
+    match kanji_reading_parts(&kanji, ruby) {
+        Some(parts) if parts.iter().all(|(kind, _)| *kind == parts[0].0) => parts[0].0,
+        Some(_) => "mixed",
+        None => "unknown",
+    }
+}
+
+/// Keeps ruby only on segments containing one of `targets`. A multi-kanji
+/// segment is first divided per kanji when `KANJI_READINGS` can split its
+/// reading (日月 -> 日/ニチ 月/ゲツ); otherwise it keeps its ruby whole.
+pub(crate) fn target_kanji_segments(segments: Vec<RubySegment>, targets: &[char]) -> Vec<RubySegment> {
+    let is_target = |seg: &RubySegment| seg.text.chars().any(|c| targets.contains(&c));
+    let mut out = Vec::with_capacity(segments.len());
+    for seg in segments {
+        let kanji: Vec<char> = seg.text.chars().collect();
+        let parts = if kanji.len() > 1 && is_target(&seg) && kanji.iter().all(|&c| is_kanji(c)) {
+            kanji_reading_parts(&kanji, &seg.ruby)
+        } else {
+            None
+        };
+        match parts {
+            Some(parts) => out.extend(kanji.iter().zip(parts).map(|(c, (_, part))| RubySegment {
+                text: c.to_string(),
+                ruby: part.to_string(),
+                ..seg.clone()
+            })),
+            None => out.push(seg),
+        }
+    }
+    for seg in out.iter_mut().filter(|seg| !is_target(seg)) {
+        seg.ruby.clear();
+    }
+    out
+}
+
+/// Words read as a whole (jukujikun and ateji) whose reading cannot be
+/// divided among their kanji.
+pub(crate) const JUKUJIKUN: &[(&str, &str)] = &[
+    ("今日", "キョウ"), ("明日", "アシタ"), ("昨日", "キノウ"), ("今朝", "ケサ"),
+    ("大人", "オトナ"), ("一人", "ヒトリ"), ("二人", "フタリ"), ("時計", "トケイ"),
+    ("眼鏡", "メガネ"), ("土産", "ミヤゲ"), ("田舎", "イナカ"), ("紅葉", "モミジ"),
+    ("珈琲", "コーヒー"), ("麦酒", "ビール"), ("煙草", "タバコ"), ("硝子", "ガラス"),
+    ("倶楽部", "クラブ"), ("型録", "カタログ"), ("天麩羅", "テンプラ"), ("合羽", "カッパ"),
+    ("煙管", "キセル"), ("如雨露", "ジョウロ"), ("背広", "セビロ"), ("浴衣", "ユカタ"),
+    // Reduplicated adverbs and onomatopoeia written in kanji.
+    ("態々", "ワザワザ"), ("益々", "マスマス"), ("愈々", "イヨイヨ"), ("偶々", "タマタマ"),
+    ("努々", "ユメユメ"), ("屡々", "シバシバ"), ("抑々", "ソモソモ"), ("中々", "ナカナカ"),
+    ("轟々", "ゴウゴウ"), ("煌々", "コウコウ"), ("滔々", "トウトウ"), ("嗚呼", "アア"),
+    ("屹度", "キット"),
+];
+
+/// Writes a kanji repeated right after itself as 々 (態態 -> 態々), so either
+/// spelling of a reduplicated word finds the same table entry.
+pub(crate) fn fold_repeated_kanji(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut prev = None;
+    for c in s.chars() {
+        out.push(if is_kanji(c) && prev == Some(c) { '々' } else { c });
+        prev = Some(c);
+    }
+    out
+}
+
+/// Table reading for a whole-word jukujikun/ateji surface, checking the
+/// user's `jukujikun_csv` before the built-in table. Reduplicated words
+/// match whether written with 々 or a repeated kanji.
+pub(crate) fn jukujikun_reading(surface: &str, params: &InputParams) -> Option<String> {
+    let folded = fold_repeated_kanji(surface);
+    let user_entry = params.jukujikun_csv.as_deref().and_then(|csv| {
+        csv.lines().find_map(|line| {
+            let (entry, reading) = line.split_once(',')?;
+            (fold_repeated_kanji(entry.trim()) == folded).then(|| reading.trim().to_string())
+        })
+    });
+    user_entry.or_else(|| {
+        JUKUJIKUN.iter().find(|(entry, _)| *entry == folded).map(|(_, reading)| reading.to_string())
+    })
+}
+
+/// Allots the morae of `ruby` to the characters of `text` as evenly as
+/// possible, following JLReq's even distribution of group ruby. When the
+/// split is uneven the earlier characters take the extra mora (3 morae over
+/// 2 characters -> 2 + 1); with fewer morae than characters the last ones
+/// get nothing.
+pub(crate) fn distribute_morae(text: &str, ruby: &str) -> Vec<String> {
+    let morae = mora_units(ruby);
+    let (n, k) = (morae.len(), text.chars().count());
+    let start = |i: usize| (i * n).div_ceil(k);
+    (0..k).map(|i| morae[start(i)..start(i + 1)].concat()).collect()
+}
+
+/// Splits segments at the given character offsets of the token surface.
+/// A split segment's ruby is divided by mora in proportion to the number of
+/// characters on each side (best-effort: each part keeps at least one mora
+/// while there are enough, and the last part takes the remainder).
+pub(crate) fn apply_split_points(segments: Vec<RubySegment>, points: &[usize]) -> Vec<RubySegment> {
+    let mut out = Vec::with_capacity(segments.len() + points.len());
+    let mut seg_start = 0;
+    for seg in segments {
+        let chars: Vec<char> = seg.text.chars().collect();
+        let seg_end = seg_start + chars.len();
+        let mut cuts: Vec<usize> = points
+            .iter()
+            .filter(|&&p| p > seg_start && p < seg_end)
+            .map(|&p| p - seg_start)
+            .collect();
+        cuts.sort_unstable();
+        cuts.dedup();
+        seg_start = seg_end;
+        if cuts.is_empty() {
+            out.push(seg);
+            continue;
+        }
+
+        let morae = mora_units(&seg.ruby);
+        let mut bounds = vec![0];
+        bounds.extend(cuts);
+        bounds.push(chars.len());
+        let mut mora_idx = 0;
+        for (part, window) in bounds.windows(2).enumerate() {
+            let is_last = part + 2 == bounds.len();
+            let take = if is_last {
+                morae.len() - mora_idx
+            } else {
+                let share = (morae.len() * window[1]).div_ceil(chars.len()).saturating_sub(mora_idx);
+                share.max(1).min(morae.len().saturating_sub(mora_idx + (bounds.len() - part - 2)))
+            };
+            out.push(RubySegment {
+                text: chars[window[0]..window[1]].iter().collect(),
+                ruby: morae[mora_idx..mora_idx + take].concat(),
+                ..seg.clone()
+            });
+            mora_idx += take;
+        }
+    }
+    out
+}
+
+/// Mono ruby: a multi-kanji segment whose reading has exactly one mora per
+/// kanji is split so each kanji carries its own mora. Failing that, the
+/// reading is divided by `KANJI_READINGS`, position by position, so a kanji
+/// that repeats gets each occurrence's own reading (日曜日 -> ニチ/ヨウ/ビ,
+/// 人々 -> ヒト/ビト). Otherwise the segment keeps its group ruby, since
+/// there is no reliable way to divide it.
+pub(crate) fn mono_ruby_segments(segments: Vec<RubySegment>) -> Vec<RubySegment> {
+    let mut out = Vec::with_capacity(segments.len());
+    for seg in segments {
+        let chars: Vec<char> = seg.text.chars().collect();
+        let all_kanji = chars.first().is_some_and(|&c| is_kanji(c)) && chars.iter().all(|&c| is_kanji(c) || c == '々');
+        if chars.len() < 2 || !all_kanji {
+            out.push(seg);
+            continue;
+        }
+        let mut morae = mora_units(&seg.ruby);
+        if morae.len() != chars.len() {
+            // 々 is looked up as the kanji it repeats.
+            let kanji: Vec<char> = chars
+                .iter()
+                .scan(' ', |prev, &c| {
+                    if c != '々' {
+                        *prev = c;
+                    }
+                    Some(*prev)
+                })
+                .collect();
+            match kanji_reading_parts(&kanji, &seg.ruby) {
+                Some(parts) => morae = parts.into_iter().map(|(_, part)| part.to_string()).collect(),
+                None => {
+                    out.push(seg);
+                    continue;
+                }
+            }
+        }
+        for (c, mora) in chars.into_iter().zip(morae) {
+            out.push(RubySegment {
+                text: c.to_string(),
+                ruby: mora,
+                ..seg.clone()
+            });
+        }
+    }
+    out
+}
+
+/// Whether `okurigana` is what a godan verb whose dictionary-form reading
+/// ends in `ending` turns that kana into when conjugated: a sound change
+/// (買う -> 買っ, 泳ぐ -> 泳い, 読む -> 読ん) or another kana of the same
+/// row (書く -> 書か, 買う -> 買わ).
+pub(crate) fn is_conjugated_ending(ending: char, okurigana: char) -> bool {
+    const ROWS: [&str; 9] = ["アイウエオワ", "カキクケコ", "ガギグゲゴ", "サシスセソ", "タチツテト", "ナニヌネノ", "バビブベボ", "マミムメモ", "ラリルレロ"];
+    if !"ウクグスツヌブムル".contains(ending) {
+        return false;
+    }
+    let okurigana = hira_to_kata(okurigana);
+    match okurigana {
+        'ッ' => matches!(ending, 'ウ' | 'ツ' | 'ル' | 'ク'),
+        'イ' if matches!(ending, 'ク' | 'グ') => true,
+        'ン' => matches!(ending, 'ム' | 'ブ' | 'ヌ'),
+        _ => ROWS.iter().any(|row| row.contains(ending) && row.contains(okurigana)),
+    }
+}
+
+/// Confidence of a token's ruby: the heuristic's own value when the reading
+/// was guessed, otherwise full when `segments` are clean and low when the
+/// alignment fell back.
+pub(crate) fn alignment_confidence(segments: &[RubySegment], heuristic: Option<f32>) -> f32 {
+    heuristic.unwrap_or_else(|| if is_clean_segments(segments) { 1.0 } else { LOW_CONFIDENCE })
+}
+
+/// Whether alignment placed a reading over every kanji and nothing else:
+/// no `*` reading, no kanji left bare, no kana swallowed into a kanji group.
+pub(crate) fn is_clean_alignment(surface: &str, reading: &str, params: &InputParams) -> bool {
+    reading != "*" && is_clean_segments(&build_ruby_segments(surface, reading, params))
+}
+
+fn is_clean_segments(segments: &[RubySegment]) -> bool {
+    segments.iter().all(|seg| {
+        let has_kanji = seg.text.chars().any(is_kanji);
+        if has_kanji {
+            !seg.ruby.is_empty() && seg.text.chars().all(|c| is_kanji(c) || c == '々')
+        } else {
+            seg.ruby.is_empty()
+        }
+    })
+}
+
+/// Moves the reading of each run of ruby-bearing segments onto the run's
+/// first character and leaves the rest of the run bare (漢字 -> 漢[かんじ]字).
+pub(crate) fn lead_kanji_only(segments: Vec<RubySegment>) -> Vec<RubySegment> {
+    let mut out: Vec<RubySegment> = Vec::new();
+    let mut run_lead: Option<usize> = None;
+
+    for seg in segments {
+        if seg.ruby.is_empty() {
+            run_lead = None;
+            out.push(seg);
+            continue;
+        }
+
+        if let Some(lead) = run_lead {
+            out[lead].ruby.push_str(&seg.ruby);
+            out.push(RubySegment {
+                text: seg.text,
+                ruby: "".to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let first_len = seg.text.chars().next().map_or(0, char::len_utf8);
+        run_lead = Some(out.len());
+        out.push(RubySegment {
+            text: seg.text[..first_len].to_string(),
+            ruby: seg.ruby,
+            ..Default::default()
+        });
+        if first_len < seg.text.len() {
+            out.push(RubySegment {
+                text: seg.text[first_len..].to_string(),
+                ruby: "".to_string(),
+                ..Default::default()
+            });
+        }
+    }
+    out
+}
+
+/// Spells out kana iteration marks in a reading: ヽ/ゝ repeat the previous
+/// kana unvoiced and ヾ/ゞ voiced (イスヾ -> イスズ, バヽ -> バハ).
+pub(crate) fn expand_kana_iteration_marks(reading: &str) -> String {
+    let mut out = String::with_capacity(reading.len());
+    let mut prev: Option<char> = None;
+    for c in reading.chars() {
+        let voiced = match c {
+            'ヽ' | 'ゝ' => false,
+            'ヾ' | 'ゞ' => true,
+            _ => {
+                out.push(c);
+                prev = Some(c);
+                continue;
+            }
+        };
+        let Some(p) = prev else {
+            out.push(c);
+            continue;
+        };
+        // Strip any voicing first so ガヽ repeats カ and ガヾ repeats ガ.
+        let plain = [1, 2]
+            .into_iter()
+            .filter_map(|offset| (p as u32).checked_sub(offset).and_then(char::from_u32))
+            .find(|&base| {
+                compose_halfwidth_voicing(base, '\u{3099}') == Some(p) || compose_halfwidth_voicing(base, '\u{309A}') == Some(p)
+            })
+            .unwrap_or(p);
+        let repeated = if voiced {
+            compose_halfwidth_voicing(plain, '\u{3099}').unwrap_or(plain)
+        } else {
+            plain
+        };
+        out.push(repeated);
+    }
+    out
+}
+
+/// Aligns each side of a ・ (ジョン・スミス) separately; the dot itself never
+/// takes ruby. The reading is divided at its own ・ when it has as many,
+/// otherwise kana-only parts claim their own spelling from it and at most one
+/// other part takes the rest. None when the reading cannot be divided.
+pub(crate) fn nakaguro_segments(surface: &str, reading: &str, params: &InputParams) -> Option<Vec<RubySegment>> {
+    let parts: Vec<&str> = surface.split('・').collect();
+    let is_kana_part = |part: &str| part.chars().all(|c| is_hiragana(c) || is_katakana(c));
+
+    let part_readings: Vec<String> = if reading.split('・').count() == parts.len() {
+        reading.split('・').map(str::to_string).collect()
+    } else {
+        let read: Vec<char> = reading.chars().filter(|&c| c != '・').collect();
+        let kana_len: usize = parts.iter().filter(|p| is_kana_part(p)).map(|p| p.chars().count()).sum();
+        let other_len = read.len().checked_sub(kana_len)?;
+        if parts.iter().filter(|p| !is_kana_part(p)).count() > 1 {
+            return None;
+        }
+        let mut pos = 0;
+        let mut out = Vec::with_capacity(parts.len());
+        for part in &parts {
+            let len = if is_kana_part(part) { part.chars().count() } else { other_len };
+            let part_reading: String = read[pos..pos + len].iter().collect();
+            let spelled: String = part.chars().map(hira_to_kata).collect();
+            if is_kana_part(part) && !equal_ignoring_long_vowels(&spelled, &part_reading) {
+                return None;
+            }
+            out.push(part_reading);
+            pos += len;
+        }
+        if pos != read.len() {
+            return None;
+        }
+        out
+    };
+
+    let mut segments = Vec::new();
+    for (i, (part, part_reading)) in parts.iter().zip(&part_readings).enumerate() {
+        if i > 0 {
+            segments.push(RubySegment {
+                text: "・".to_string(),
+                ruby: "".to_string(),
+                ..Default::default()
+            });
+        }
+        if !part.is_empty() {
+            segments.extend(build_ruby_segments(part, part_reading, params));
+        }
+    }
+    Some(segments)
+}
+
+/// Bidirectional alignment: kana that match at the start (お座り) and at the
+/// end (落ち着く) of both surface and reading are peeled off as bare
+/// segments, and only the middle is aligned left to right. None when
+/// neither end anchors.
+pub(crate) fn anchored_segments(surface: &str, reading: &str, params: &InputParams) -> Option<Vec<RubySegment>> {
+    let sur: Vec<char> = surface.chars().collect();
+    let read: Vec<char> = reading.chars().collect();
+    let anchors = |s: char, r: char| {
+        (is_hiragana(s) || is_katakana(s)) && kana_eq(hira_to_kata(s), r, params.loose_kana_size)
+    };
+
+    // Both ends keep at least one character in the middle for the kanji.
+    let mut head = 0;
+    while sur.len() - head > 1 && read.len() - head > 1 && anchors(sur[head], read[head]) {
+        head += 1;
+    }
+    let mut tail = 0;
+    while sur.len() - head - tail > 1
+        && read.len() - head - tail > 1
+        && anchors(sur[sur.len() - 1 - tail], read[read.len() - 1 - tail])
+    {
+        tail += 1;
+    }
+    if head == 0 && tail == 0 {
+        return None;
+    }
+
+    let bare = |chars: &[char]| RubySegment {
+        text: chars.iter().collect(),
+        ruby: "".to_string(),
+        ..Default::default()
+    };
+    let middle: String = sur[head..sur.len() - tail].iter().collect();
+    let middle_reading: String = read[head..read.len() - tail].iter().collect();
+    let mut segments = Vec::new();
+    if head > 0 {
+        segments.push(bare(&sur[..head]));
+    }
+    segments.extend(build_ruby_segments(&middle, &middle_reading, params));
+    if tail > 0 {
+        segments.push(bare(&sur[sur.len() - tail..]));
+    }
+    Some(segments)
+}
+
+pub(crate) fn build_ruby_segments(surface: &str, reading: &str, params: &InputParams) -> Vec<RubySegment> {
+    let expanded;
+    let reading = if reading.contains(['ヽ', 'ヾ', 'ゝ', 'ゞ']) {
+        expanded = expand_kana_iteration_marks(reading);
+        expanded.as_str()
+    } else {
+        reading
+    };
+    // A malformed reading that starts with ー has no vowel to lengthen; drop the mark.
+    let reading = reading.trim_start_matches(['ー', '\u{FF70}']);
+    // A "reading" containing kanji (user dictionaries used for reverse lookup,
+    // e.g. すし -> 寿司) is not a pronunciation, so the surface stays bare.
+    if reading == "*" || reading.is_empty() || reading.chars().any(is_kanji) {
+        return vec![RubySegment {
+            text: surface.to_string(),
+            ruby: "".to_string(),
+            ..Default::default()
+        }];
+    }
+
+    // A ー lengthening hiragana or kanji is informal (ありがとー, 凄ーい): the
+    // surface is aligned without it and it goes back in as bare text.
+    if params.tolerate_lengthening {
+        let chars: Vec<char> = surface.chars().collect();
+        let mut marks = Vec::new();
+        for (i, &c) in chars.iter().enumerate().skip(1) {
+            let prev = chars[i - 1];
+            if matches!(c, 'ー' | '\u{FF70}') && (is_hiragana(prev) || is_kanji(prev) || marks.last() == Some(&(i - 1))) {
+                marks.push(i);
+            }
+        }
+        if !marks.is_empty() {
+            let stripped: String = chars.iter().enumerate().filter(|(i, _)| !marks.contains(i)).map(|(_, c)| c).collect();
+            return reinsert_lengthening(build_ruby_segments(&stripped, reading, params), &chars, &marks);
+        }
+    }
+
+    // Iteration marks in the surface (こゝろ, いすゞ) are aligned as the kana
+    // they stand for; the segments then get the marks back, one char each.
+    if surface.contains(['ヽ', 'ヾ', 'ゝ', 'ゞ']) {
+        let spelled = expand_kana_iteration_marks(surface);
+        if spelled != surface && spelled.chars().count() == surface.chars().count() {
+            let mut original = surface.chars();
+            return build_ruby_segments(&spelled, reading, params)
+                .into_iter()
+                .map(|seg| RubySegment {
+                    text: original.by_ref().take(seg.text.chars().count()).collect(),
+                    ..seg
+                })
+                .collect();
+        }
+    }
+
+    if annotates_kana(surface, params) {
+        return vec![RubySegment {
+            text: surface.to_string(),
+            ruby: reading.to_string(),
+            ..Default::default()
+        }];
+    }
+
+    if equal_ignoring_long_vowels(surface, reading) {
+        return vec![RubySegment {
+            text: surface.to_string(),
+            ruby: "".to_string(),
+            ..Default::default()
+        }];
+    }
+
+    // All-kanji compounds (申込, 受付) have no kana to anchor a split, so the
+    // whole reading goes over the whole word. This also covers a lone kanji,
+    // whatever the reading's length (木 キ, 承 ショウ, 志 ココロザシ).
+    if surface.chars().all(|c| is_kanji(c) || c == '々') {
+        return vec![RubySegment {
+            text: surface.to_string(),
+            ruby: reading.to_string(),
+            ..Default::default()
+        }];
+    }
+
+    if surface.contains('・') {
+        if let Some(segments) = nakaguro_segments(surface, reading, params) {
+            return segments;
+        }
+    }
+
+    if params.align == "bidirectional" {
+        if let Some(segments) = anchored_segments(surface, reading, params) {
+            return segments;
+        }
+    }
+
+    let sur_chars: Vec<char> = surface.chars().collect();
+    let read_chars: Vec<char> = reading.chars().collect();
+    
+    let mut segments = Vec::new();
+    let mut buffer_s = String::new();
+    let mut r_idx = 0;
+
+    let mut skip_to = 0;
+
+    for (i, &s_char) in sur_chars.iter().enumerate() {
+        if i < skip_to {
+            continue;
+        }
+        let s_kata = hira_to_kata(s_char);
+        let is_hiragana = s_char != s_kata;
+
+        // A katakana stem (メールする, メール便) matching the reading is copied
+        // through bare so a following kanji's reading never spreads onto it.
+        if buffer_s.is_empty() && is_katakana(s_char) && read_chars.get(r_idx) == Some(&s_char) {
+            segments.push(RubySegment {
+                text: s_char.to_string(),
+                ruby: "".to_string(),
+                ..Default::default()
+            });
+            r_idx += 1;
+            continue;
+        }
+
+        // A title-case Latin word in a mixed-script name (さくらSakura櫻) is
+        // taken as romaji: when the reading spells it, the word stays bare as
+        // one segment and only the kanji around it get ruby.
+        if let Some(word_len) = title_case_word_len(&sur_chars[i..]) {
+            let word: String = sur_chars[i..i + word_len]
+                .iter()
+                .filter_map(|&c| ascii_latin(c))
+                .map(|c| c.to_ascii_lowercase())
+                .collect();
+            let remaining = &read_chars[r_idx.min(read_chars.len())..];
+            let found = romaji_in_reading(remaining, &word, usize::from(!buffer_s.is_empty()))
+                .filter(|&(pos, _)| pos == 0 || !buffer_s.is_empty());
+            if let Some((pos, len)) = found {
+                if !buffer_s.is_empty() {
+                    segments.push(RubySegment {
+                        text: std::mem::take(&mut buffer_s),
+                        ruby: remaining[..pos].iter().collect(),
+                        ..Default::default()
+                    });
+                }
+                segments.push(RubySegment {
+                    text: sur_chars[i..i + word_len].iter().collect(),
+                    ruby: "".to_string(),
+                    ..Default::default()
+                });
+                r_idx += pos + len;
+                skip_to = i + word_len;
+                continue;
+            }
+        }
+
+        // Latin letters (CD版, T細胞, ＩＴ化) never take ruby. Whether the reading
+        // spells them literally or by letter name, that part of the reading is skipped.
+        if let Some(latin) = ascii_latin(s_char) {
+            let remaining = &read_chars[r_idx.min(read_chars.len())..];
+            let found = latin_in_reading(remaining, latin);
+            if found.is_some() || buffer_s.is_empty() {
+                if let Some((pos, len)) = found {
+                    if !buffer_s.is_empty() {
+                        segments.push(RubySegment {
+                            text: std::mem::take(&mut buffer_s),
+                            ruby: remaining[..pos].iter().collect(),
+                            ..Default::default()
+                        });
+                    }
+                    r_idx += pos + len;
+                }
+                segments.push(RubySegment {
+                    text: s_char.to_string(),
+                    ruby: "".to_string(),
+                    ..Default::default()
+                });
+                continue;
+            }
+        }
+
+        // A vowel kana lengthening the previous kana (ありがとお, すごおい) stays
+        // bare, taking the reading's standard long vowel (アリガトウ) if it
+        // spells one and nothing if it goes straight on (スゴイ).
+        if params.tolerate_lengthening && is_hiragana && buffer_s.is_empty() && i > 0 {
+            let next = read_chars.get(r_idx).copied();
+            if let (Some(vowel), Some(r)) = (kana_vowel(sur_chars[i - 1]), next) {
+                let after = sur_chars.get(i + 1).map(|&c| hira_to_kata(c));
+                if r != s_kata && lengthens(vowel, s_kata) && (lengthens(vowel, r) || after == Some(r)) {
+                    segments.push(RubySegment {
+                        text: s_char.to_string(),
+                        ruby: "".to_string(),
+                        ..Default::default()
+                    });
+                    if lengthens(vowel, r) {
+                        r_idx += 1;
+                    }
+                    continue;
+                }
+            }
+        }
+
+        if is_hiragana {
+            if r_idx < read_chars.len() {
+                let remaining_reading = &read_chars[r_idx..];
+
+                // A pending kanji run needs at least one kana of its own, so the
+                // okurigana is searched for after it (言い合い: 言 -> イ, not "").
+                // The kanji takes whatever precedes the okurigana the surface
+                // actually spells, so 行なう gives 行 -> オコ and 行う 行 -> オコナ.
+                let min_kanji_len = usize::from(!buffer_s.is_empty());
+                // A small kana stuck to a kanji in stylized spellings (私ゃ) is
+                // its okurigana even when the reading spells it full-size (ワタシヤ).
+                let loose = params.loose_kana_size || (!buffer_s.is_empty() && is_small_kana(s_char));
+                let found = remaining_reading
+                    .iter()
+                    .skip(min_kanji_len)
+                    .position(|&c| kana_eq(c, s_kata, loose))
+                    .map(|pos| pos + min_kanji_len);
+
+                if let Some(pos_in_remaining) = found {
+                    let kanji_reading_len = pos_in_remaining;
+                    
+                    if !buffer_s.is_empty() {
+                        let end_idx = r_idx + kanji_reading_len;
+                        if end_idx <= read_chars.len() {
+                            let kanji_reading: String = read_chars[r_idx..end_idx].iter().collect();
+                            segments.push(RubySegment {
+                                text: buffer_s.clone(),
+                                ruby: kanji_reading,
+                                ..Default::default()
+                            });
+                        }
+                        buffer_s.clear();
+                    }
+
+                    segments.push(RubySegment {
+                        text: s_char.to_string(),
+                        ruby: "".to_string(),
+                        ..Default::default()
+                    });
+
+                    r_idx += kanji_reading_len + 1;
+                    continue;
+                }
+            }
+        }
+        
+        buffer_s.push(s_char);
+    }
+
+    if !buffer_s.is_empty() {
+        let remaining_ruby: String = if r_idx < read_chars.len() {
+            read_chars[r_idx..].iter().collect()
+        } else {
+            "".to_string()
+        };
+        // Hiragana left in the buffer were not found in the reading (it omits
+        // trailing okurigana, e.g. 扱い read アツカ), so they stay bare instead
+        // of sharing the kanji's ruby.
+        let head_len = buffer_s.trim_end_matches(is_hiragana).len();
+        if head_len > 0 && head_len < buffer_s.len() {
+            let tail = buffer_s.split_off(head_len);
+            let mut remaining_ruby = remaining_ruby;
+            // A reading given in dictionary form (買って read カウ) ends in the
+            // kana the conjugation replaced; it belongs to no kanji.
+            if let (Some(ending), Some(okurigana)) = (remaining_ruby.chars().last(), tail.chars().next()) {
+                if remaining_ruby.chars().nth(1).is_some() && is_conjugated_ending(ending, okurigana) {
+                    remaining_ruby.pop();
+                }
+            }
+            segments.push(RubySegment {
+                text: buffer_s,
+                ruby: remaining_ruby,
+                ..Default::default()
+            });
+            segments.push(RubySegment {
+                text: tail,
+                ruby: "".to_string(),
+                ..Default::default()
+            });
+        } else {
+            segments.push(RubySegment {
+                text: buffer_s,
+                ruby: remaining_ruby,
+                ..Default::default()
+            });
+        }
+    }
+
+    segments
+}
+
+/// How the particles は, へ and を are pronounced, for reading output.
+pub(crate) fn particle_reading(surface: &str) -> Option<&'static str> {
+    match surface {
+        "は" => Some("ワ"),
+        "へ" => Some("エ"),
+        "を" => Some("オ"),
+        _ => None,
+    }
+}
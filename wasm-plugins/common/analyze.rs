@@ -0,0 +1,1185 @@
+//! Analysis shared by every dictionary plugin: tokenizing, the per-token
+//! reading heuristics and the option-driven passes over the token list. A
+//! plugin supplies its dictionary's side of a token through `DictionaryToken`.
+
+use super::*;
+
+/// The dictionary-specific side of a token, implemented by each plugin's
+/// `TokenInfo`. Everything else in the analysis is shared.
+pub(crate) trait DictionaryToken: Sized {
+    /// Starts the token for a dictionary entry, returning it with the reading
+    /// to align (`*` when the entry has none).
+    fn from_entry(surface: &str, details: Vec<String>, params: &InputParams) -> (Self, String);
+
+    /// Starts the token for untokenized whitespace.
+    fn whitespace(text: &str) -> Self;
+
+    /// Part of speech and its first subcategory.
+    fn pos(&self) -> (&str, &str);
+
+    /// Dictionary form, if the dictionary has one.
+    fn base_form(&self) -> Option<&str>;
+
+    /// Feature columns in dictionary order, as MeCab prints them.
+    fn features(&self) -> Vec<&str>;
+
+    /// Called when a heuristic replaced the dictionary reading.
+    fn heuristic_reading(&mut self, _reading: &str) {}
+
+    /// Called when a post-pass merged tokens under a reading from the text
+    /// or the user dictionary.
+    fn replace_reading(&mut self, _reading: &str) {}
+
+    /// Switches `details` to an object keyed by column name (`details_map`).
+    fn details_as_map(&mut self) {}
+}
+
+/// A token's feature columns: the raw list, or keyed by `DETAIL_SCHEMA` and
+/// serialized as a JSON object (`details_map`).
+#[derive(Clone)]
+pub(crate) enum Details {
+    List(Vec<String>),
+    Map(Vec<(&'static str, String)>),
+}
+
+impl Default for Details {
+    fn default() -> Self {
+        Details::List(Vec::new())
+    }
+}
+
+impl Details {
+    pub(crate) fn into_map(self) -> Self {
+        match self {
+            Details::List(list) => Details::Map(DETAIL_SCHEMA.iter().copied().zip(list).collect()),
+            map => map,
+        }
+    }
+
+    pub(crate) fn features(&self) -> Vec<&str> {
+        match self {
+            Details::List(list) => list.iter().map(String::as_str).collect(),
+            Details::Map(entries) => entries.iter().map(|(_, value)| value.as_str()).collect(),
+        }
+    }
+}
+
+impl Serialize for Details {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Details::List(list) => list.serialize(serializer),
+            Details::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// Lindera word id: the entry's index in the system dictionary, or in the
+/// user dictionary when `system` is false. Stable for a given dictionary build.
+#[derive(Serialize, Clone)]
+pub(crate) struct EntryId {
+    pub(crate) id: u32,
+    pub(crate) system: bool,
+}
+
+/// The `output_kana` for a token: its `kana_by_pos` entry, the POS with
+/// subcategory taking precedence over the bare POS, else the global one.
+pub(crate) fn output_kana_for<'a>(pos: &str, sub_pos: &str, params: &'a InputParams) -> &'a str {
+    params
+        .kana_by_pos
+        .as_ref()
+        .and_then(|map| map.get(&format!("{}-{}", pos, sub_pos)).or_else(|| map.get(pos)))
+        .map_or(params.output_kana.as_str(), String::as_str)
+}
+
+/// Puts `ruby` over the number that ends `result_list`, first merging the
+/// `count` tokens the tokenizer split it into (二 + 十 before 日) so the
+/// reading spans the whole number.
+pub(crate) fn set_number_ruby(result_list: &mut Vec<TokenInfo>, count: usize, ruby: &str) {
+    let start = result_list.len().saturating_sub(count);
+    let merged = result_list.drain(start..).reduce(|mut acc, token| {
+        acc.surface.push_str(&token.surface);
+        acc.byte_end = token.byte_end;
+        acc
+    });
+    let Some(mut merged) = merged else {
+        return;
+    };
+    merged.ruby_segments = vec![RubySegment {
+        text: merged.surface.clone(),
+        ruby: ruby.to_string(),
+        ..Default::default()
+    }];
+    result_list.push(merged);
+}
+
+/// Splits a numeral + counter token (三人/サンニン) into 三/サン and 人/ニン.
+/// Only tokens carrying a single ruby segment are split, and only when a
+/// numeral reading is a proper prefix of the token reading.
+pub(crate) fn split_number_counter(token: &TokenInfo) -> Option<(TokenInfo, TokenInfo)> {
+    let [segment] = token.ruby_segments.as_slice() else {
+        return None;
+    };
+    if segment.ruby.is_empty() || segment.text != token.surface {
+        return None;
+    }
+
+    let numeral_len: usize = token.surface.chars().take_while(|&c| is_kanji_numeral(c)).map(char::len_utf8).sum();
+    if numeral_len == 0 || numeral_len == token.surface.len() {
+        return None;
+    }
+    let (numeral, counter) = token.surface.split_at(numeral_len);
+
+    let numeral_reading = numeral_reading_candidates(numeral)
+        .into_iter()
+        .filter(|r| segment.ruby.starts_with(r.as_str()) && segment.ruby.len() > r.len())
+        .max_by_key(|r| r.len())?;
+    let counter_reading = segment.ruby[numeral_reading.len()..].to_string();
+
+    let numeral_token = TokenInfo {
+        surface: numeral.to_string(),
+        ruby_segments: vec![RubySegment {
+            text: numeral.to_string(),
+            ruby: numeral_reading,
+            ..Default::default()
+        }],
+        byte_end: token.byte_start + numeral_len,
+        ..token.clone()
+    };
+    let counter_token = TokenInfo {
+        surface: counter.to_string(),
+        ruby_segments: vec![RubySegment {
+            text: counter.to_string(),
+            ruby: counter_reading,
+            ..Default::default()
+        }],
+        byte_start: token.byte_start + numeral_len,
+        ..token.clone()
+    };
+    Some((numeral_token, counter_token))
+}
+
+/// A reading the author wrote after a kanji run, as in 漢字（かんじ）. Spans
+/// are byte ranges of the kanji run and of the whole parenthetical.
+pub(crate) struct InlineReading {
+    pub(crate) base: (usize, usize),
+    pub(crate) paren: (usize, usize),
+    pub(crate) reading: String,
+}
+
+/// Finds `kanji（kana）` and `kanji(kana)` annotations. Parentheticals that are
+/// empty, hold anything but kana, nest, or are never closed are left alone.
+pub(crate) fn inline_readings(text: &str) -> Vec<InlineReading> {
+    let mut found = Vec::new();
+    for (open_at, open) in text.char_indices() {
+        let close = match open {
+            '（' => '）',
+            '(' => ')',
+            _ => continue,
+        };
+        let Some((base_start, _)) = text[..open_at]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_kanji(c) || c == '々')
+            .last()
+        else {
+            continue;
+        };
+        let inner_start = open_at + open.len_utf8();
+        let Some(inner_len) = text[inner_start..].find(close) else {
+            continue;
+        };
+        let reading = &text[inner_start..inner_start + inner_len];
+        if reading.is_empty() || !reading.chars().all(|c| is_hiragana(c) || is_katakana(c)) {
+            continue;
+        }
+        found.push(InlineReading {
+            base: (base_start, open_at),
+            paren: (open_at, inner_start + inner_len + close.len_utf8()),
+            reading: reading.chars().map(hira_to_kata).collect(),
+        });
+    }
+    found
+}
+
+/// Moves author-written readings into ruby: the tokens covering the kanji run
+/// merge into one token whose ruby is the parenthetical, and the tokens of the
+/// parenthetical are dropped. Annotations whose spans cut through a token are
+/// skipped. Offsets refer to `text`.
+pub(crate) fn strip_inline_readings(mut result_list: Vec<TokenInfo>, text: &str) -> Vec<TokenInfo> {
+    for inline in inline_readings(text).into_iter().rev() {
+        let (Some(first), Some(last), Some(paren_last)) = (
+            result_list.iter().position(|t| t.byte_end > inline.base.0),
+            result_list.iter().position(|t| t.byte_end == inline.base.1),
+            result_list.iter().position(|t| t.byte_end == inline.paren.1),
+        ) else {
+            continue;
+        };
+        if first > last {
+            continue;
+        }
+
+        let head = &result_list[first];
+        let surface = text[head.byte_start..inline.base.1].to_string();
+        let prefix_len = inline.base.0 - head.byte_start;
+        let mut ruby_segments = Vec::new();
+        if prefix_len > 0 {
+            ruby_segments.push(RubySegment {
+                text: surface[..prefix_len].to_string(),
+                ruby: "".to_string(),
+                ..Default::default()
+            });
+        }
+        ruby_segments.push(RubySegment {
+            text: surface[prefix_len..].to_string(),
+            ruby: inline.reading.clone(),
+            ..Default::default()
+        });
+        let mut merged = TokenInfo {
+            surface,
+            ruby_segments,
+            full_reading: head.full_reading.as_ref().map(|_| inline.reading.clone()),
+            byte_end: inline.base.1,
+            ..head.clone()
+        };
+        merged.replace_reading(&inline.reading);
+        result_list.splice(first..=paren_last, [merged]);
+    }
+    result_list
+}
+
+/// `(surface, reading)` of every user dictionary row that has a reading, in
+/// either the simple (`surface,pos,reading`) or the detailed format.
+pub(crate) fn user_dict_entries(csv_data: &str) -> Vec<(&str, &str)> {
+    csv_data
+        .lines()
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split(',').collect();
+            let reading = if cols.len() > 3 { cols.get(4 + READING_INDEX) } else { cols.get(2) }?;
+            Some((cols[0].trim(), reading.trim()))
+        })
+        .filter(|(surface, reading)| !surface.is_empty() && !reading.is_empty() && *reading != "*")
+        .collect()
+}
+
+/// Merges runs of two or more tokens that together spell a user dictionary
+/// surface (the tokenizer split the entry) back into one token carrying the
+/// entry's reading as group ruby. The longest matching run wins.
+pub(crate) fn merge_user_dict_splits(result_list: Vec<TokenInfo>, entries: &[(&str, &str)], params: &InputParams) -> Vec<TokenInfo> {
+    let mut out: Vec<TokenInfo> = Vec::with_capacity(result_list.len());
+    let mut i = 0;
+    while i < result_list.len() {
+        let matched = entries
+            .iter()
+            .filter_map(|&(surface, reading)| {
+                let mut joined = String::new();
+                for (n, token) in result_list[i..].iter().enumerate() {
+                    joined.push_str(&token.surface);
+                    if joined == surface {
+                        return (n > 0).then_some((n + 1, surface, reading));
+                    }
+                    if !surface.starts_with(joined.as_str()) {
+                        return None;
+                    }
+                }
+                None
+            })
+            .max_by_key(|&(len, _, _)| len);
+
+        let head = &result_list[i];
+        let Some((len, surface, reading)) = matched.filter(|_| !is_suppressed(head.byte_start, params)) else {
+            out.push(head.clone());
+            i += 1;
+            continue;
+        };
+        let ruby = if surface.chars().any(is_kanji) { reading.to_string() } else { String::new() };
+        let mut merged = TokenInfo {
+            surface: surface.to_string(),
+            ruby_segments: vec![RubySegment {
+                text: surface.to_string(),
+                ruby,
+                ..Default::default()
+            }],
+            full_reading: head.full_reading.as_ref().map(|_| reading.to_string()),
+            byte_end: result_list[i + len - 1].byte_end,
+            ..head.clone()
+        };
+        merged.replace_reading(reading);
+        out.push(merged);
+        i += len;
+    }
+    out
+}
+
+/// Splits a token whose segments contain a standalone ・ into one token per
+/// side plus a symbol token for each dot (`split_on_nakaguro`). A ruby-less
+/// segment such as a katakana name is first cut at its dots.
+pub(crate) fn split_on_nakaguro(mut token: TokenInfo) -> Vec<TokenInfo> {
+    token.ruby_segments = token
+        .ruby_segments
+        .into_iter()
+        .flat_map(|seg| {
+            if !seg.ruby.is_empty() || seg.text == "・" || !seg.text.contains('・') {
+                return vec![seg];
+            }
+            seg.text
+                .split_inclusive('・')
+                .flat_map(|piece| match piece.strip_suffix('・') {
+                    Some(side) => vec![side, "・"],
+                    None => vec![piece],
+                })
+                .filter(|text| !text.is_empty())
+                .map(|text| RubySegment {
+                    text: text.to_string(),
+                    ..seg.clone()
+                })
+                .collect()
+        })
+        .collect();
+    if token.surface == "・" || !token.ruby_segments.iter().any(|seg| seg.text == "・") {
+        return vec![token];
+    }
+    let mut out = Vec::new();
+    let mut start = token.byte_start;
+    let mut part: Vec<RubySegment> = Vec::new();
+    let flush = |part: &mut Vec<RubySegment>, start: &mut usize, out: &mut Vec<TokenInfo>, kind: &'static str| {
+        if part.is_empty() {
+            return;
+        }
+        let surface: String = part.iter().map(|seg| seg.text.as_str()).collect();
+        let byte_end = *start + surface.len();
+        out.push(TokenInfo {
+            surface,
+            ruby_segments: std::mem::take(part),
+            kind,
+            byte_start: *start,
+            byte_end,
+            ..token.clone()
+        });
+        *start = byte_end;
+    };
+    for seg in token.ruby_segments.clone() {
+        if seg.text == "・" {
+            flush(&mut part, &mut start, &mut out, token.kind);
+            flush(&mut vec![seg], &mut start, &mut out, "symbol");
+        } else {
+            part.push(seg);
+        }
+    }
+    flush(&mut part, &mut start, &mut out, token.kind);
+    out
+}
+
+/// Other readings the system dictionary lists for exactly `surface`, skipping
+/// `*` and the chosen reading (see `rank_alternatives`).
+pub(crate) fn reading_alternatives(dictionary: &Dictionary, surface: &str, chosen: &str, max: Option<usize>) -> Vec<String> {
+    let candidates = dictionary
+        .prefix_dictionary
+        .prefix(surface)
+        .filter(|(len, _)| *len == surface.len())
+        .filter_map(|(_, entry)| {
+            let details = dictionary.word_details(entry.word_id.id as usize);
+            details.get(READING_INDEX).map(|reading| (entry.word_cost, reading.to_string()))
+        });
+    rank_alternatives(candidates, chosen, max)
+}
+
+/// Drops `*` and `chosen` from `(word cost, reading)` candidates. Duplicates
+/// keep their lowest cost; the list is ordered by cost, then by reading
+/// (roughly gojūon order for katakana), and capped at `max` entries.
+pub(crate) fn rank_alternatives(candidates: impl IntoIterator<Item = (i16, String)>, chosen: &str, max: Option<usize>) -> Vec<String> {
+    let mut alternatives: Vec<(i16, String)> = Vec::new();
+    for (cost, reading) in candidates {
+        if reading == "*" || reading == chosen {
+            continue;
+        }
+        match alternatives.iter_mut().find(|(_, r)| *r == reading) {
+            Some(existing) => existing.0 = existing.0.min(cost),
+            None => alternatives.push((cost, reading)),
+        }
+    }
+    alternatives.sort();
+    alternatives.truncate(max.unwrap_or(usize::MAX));
+    alternatives.into_iter().map(|(_, reading)| reading).collect()
+}
+
+/// One system dictionary entry competing at a position of the input.
+#[derive(Serialize, Clone)]
+pub(crate) struct LatticeNode {
+    pub(crate) surface: String,
+    pub(crate) reading: String,
+    pub(crate) cost: i16,
+}
+
+/// System dictionary entries whose surface is a prefix of `text`, i.e. the
+/// lattice nodes lindera weighed at this position. Lindera does not expose
+/// its internal lattice or n-best paths, so this is rebuilt from the prefix
+/// dictionary and lists word costs only, without connection costs.
+pub(crate) fn lattice_nodes(dictionary: &Dictionary, text: &str) -> Vec<LatticeNode> {
+    dictionary
+        .prefix_dictionary
+        .prefix(text)
+        .map(|(len, entry)| {
+            let details = dictionary.word_details(entry.word_id.id as usize);
+            LatticeNode {
+                surface: text[..len].to_string(),
+                reading: details.get(READING_INDEX).unwrap_or(&"*").to_string(),
+                cost: entry.word_cost,
+            }
+        })
+        .collect()
+}
+
+/// Validates the detailed user dictionary rows (`surface,left_id,right_id,cost,...`)
+/// and clamps out-of-range costs to the i16 range lindera stores. Lower costs make
+/// an entry more likely to be chosen. Simple three-column rows pass through unchanged.
+pub(crate) fn normalize_user_dict_csv(csv_data: &str) -> Result<String, String> {
+    let mut rows = Vec::new();
+    for (line_no, line) in csv_data.lines().enumerate() {
+        let mut cols: Vec<String> = line.split(',').map(str::to_string).collect();
+        if cols.len() > 3 {
+            for (idx, name) in [(1, "left_id"), (2, "right_id"), (3, "cost")] {
+                let value: i64 = cols[idx].trim().parse().map_err(|_| {
+                    format!("Error: User dictionary row {}: {} must be an integer, got {:?}", line_no + 1, name, cols[idx])
+                })?;
+                let value = if name == "cost" {
+                    value.clamp(i16::MIN as i64, i16::MAX as i64)
+                } else if value < 0 {
+                    return Err(format!("Error: User dictionary row {}: {} must not be negative", line_no + 1, name));
+                } else {
+                    value
+                };
+                cols[idx] = value.to_string();
+            }
+        }
+        rows.push(cols.join(","));
+    }
+    Ok(rows.join("\n"))
+}
+
+pub(crate) fn whitespace_token(gap_text: String, byte_start: usize, kind: &'static str) -> TokenInfo {
+    TokenInfo {
+        surface: gap_text.clone(),
+        ruby_segments: vec![RubySegment {
+            text: gap_text.clone(),
+            ruby: "".to_string(),
+            ..Default::default()
+        }],
+        kind,
+        byte_start,
+        byte_end: byte_start + gap_text.len(),
+        ..TokenInfo::whitespace(&gap_text)
+    }
+}
+
+pub(crate) fn token_kind(surface: &str) -> &'static str {
+    if is_symbol_token(surface) {
+        "symbol"
+    } else {
+        "word"
+    }
+}
+
+/// Tokens made only of punctuation-like marks (～, 〜, ・・・, ーー) that are
+/// neither kanji nor ordinary kana.
+pub(crate) fn is_symbol_token(surface: &str) -> bool {
+    !surface.is_empty()
+        && surface
+            .chars()
+            .all(|c| !c.is_whitespace() && (!c.is_alphanumeric() || matches!(c, 'ー' | '\u{FF70}')))
+}
+
+#[derive(PartialEq)]
+pub(crate) enum CharClass {
+    Japanese,
+    Western,
+    Other,
+}
+
+pub(crate) fn char_class(c: char) -> CharClass {
+    if is_kanji(c) || is_hiragana(c) || is_katakana(c) || c == '々' {
+        CharClass::Japanese
+    } else if c.is_ascii_alphanumeric() {
+        CharClass::Western
+    } else {
+        CharClass::Other
+    }
+}
+
+/// JLReq-style spacing between two adjacent characters: a quarter-em space
+/// where Japanese text meets Latin letters or digits (日本語 ABC, 約 10), and
+/// none elsewhere, including between digits and units (10kg) and around
+/// punctuation or existing whitespace.
+pub(crate) fn space_between(prev: char, next: char) -> &'static str {
+    match (char_class(prev), char_class(next)) {
+        (CharClass::Japanese, CharClass::Western) | (CharClass::Western, CharClass::Japanese) => "quarter",
+        _ => "none",
+    }
+}
+
+/// Pushes an untokenized gap, giving each full-width space (U+3000) its own
+/// `ideographic_space` token so indentation survives as a distinct unit.
+pub(crate) fn push_gap_tokens(result_list: &mut Vec<TokenInfo>, gap_text: &str, byte_start: usize) {
+    let mut run_start = 0;
+    for (idx, c) in gap_text.char_indices() {
+        if c == '\u{3000}' {
+            if idx > run_start {
+                result_list.push(whitespace_token(gap_text[run_start..idx].to_string(), byte_start + run_start, "whitespace"));
+            }
+            result_list.push(whitespace_token(c.to_string(), byte_start + idx, "ideographic_space"));
+            run_start = idx + c.len_utf8();
+        }
+    }
+    if run_start < gap_text.len() {
+        result_list.push(whitespace_token(gap_text[run_start..].to_string(), byte_start + run_start, "whitespace"));
+    }
+}
+
+pub(crate) fn analyze_tokens(params: &InputParams) -> Result<Vec<TokenInfo>, String> {
+    let tokenizer = build_tokenizer(params)?;
+    let Some((start, end)) = params.range else {
+        return analyze_text(&tokenizer, &params.text, 0, params);
+    };
+    if start > end {
+        return Err("Error: range start must not exceed its end".to_string());
+    }
+
+    let (start, end) = snap_range(&params.text, start, end);
+    let mut window_params = params.clone();
+    if let Some(ranges) = window_params.suppress_ranges.as_mut() {
+        for (range_start, range_end) in ranges.iter_mut() {
+            *range_start = range_start.saturating_sub(start);
+            *range_end = range_end.saturating_sub(start);
+        }
+    }
+    analyze_text(&tokenizer, &params.text[start..end], start, &window_params)
+}
+
+/// Widens a byte window to whole sentences so no token is cut in half: the
+/// start moves back to just after the previous 。, ！, ？ or line break, and
+/// the end moves forward past the next one (or to the end of the text).
+pub(crate) fn snap_range(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let is_break = |c: char| matches!(c, '。' | '！' | '？' | '!' | '?' | '\n');
+    let mut start = start.min(text.len());
+    let mut end = end.min(text.len());
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    let start = text[..start].rfind(is_break).map_or(0, |idx| idx + text[idx..].chars().next().map_or(1, char::len_utf8));
+    let end = if text[..end].ends_with(is_break) {
+        end
+    } else {
+        text[end..].find(is_break).map_or(text.len(), |idx| end + idx + text[end + idx..].chars().next().map_or(1, char::len_utf8))
+    };
+    (start, end)
+}
+
+/// Rejects option values outside their documented sets.
+pub(crate) fn validate_params(params: &InputParams) -> Result<(), String> {
+    if !["katakana", "hiragana", "romaji"].contains(&params.output_kana.as_str()) {
+        return Err("Error: output_kana must be one of: katakana, hiragana, romaji".to_string());
+    }
+    if params.kana_by_pos.iter().flat_map(|map| map.values()).any(|kana| !["katakana", "hiragana", "romaji"].contains(&kana.as_str())) {
+        return Err("Error: kana_by_pos values must be one of: katakana, hiragana, romaji".to_string());
+    }
+
+    if !["group", "mono"].contains(&params.ruby_mode.as_str()) {
+        return Err("Error: ruby_mode must be one of: group, mono".to_string());
+    }
+
+    if !["forward", "bidirectional"].contains(&params.align.as_str()) {
+        return Err("Error: align must be one of: forward, bidirectional".to_string());
+    }
+
+    if !["none", "numeric", "mark"].contains(&params.pitch_notation.as_str()) {
+        return Err("Error: pitch_notation must be one of: none, numeric, mark".to_string());
+    }
+
+    if params.reading_form.as_deref().is_some_and(|form| !["surface", "canonical"].contains(&form)) {
+        return Err("Error: reading_form must be one of: surface, canonical".to_string());
+    }
+
+    if params.output.as_deref().is_some_and(|output| !["json", "jsonl", "mecab", "aozora", "compact"].contains(&output)) {
+        return Err("Error: output must be one of: json, jsonl, mecab, aozora, compact".to_string());
+    }
+    Ok(())
+}
+
+static DICTIONARY: OnceLock<Dictionary> = OnceLock::new();
+
+/// The plugin's embedded system dictionary, loaded on first use.
+pub(crate) fn get_dictionary() -> &'static Dictionary {
+    DICTIONARY.get_or_init(|| {
+        load_dictionary(&format!("embedded://{}", DICTIONARY_NAME)).expect("Failed to load dictionary")
+    })
+}
+
+/// Validates the options and builds a tokenizer with the user dictionary, so
+/// several texts can be analyzed with one setup.
+pub(crate) fn build_tokenizer(params: &InputParams) -> Result<Tokenizer, String> {
+    validate_params(params)?;
+    let dictionary = get_dictionary().clone();
+
+    let user_dictionary = if let Some(csv_data) = &params.user_dict_csv {
+        let csv_data = normalize_user_dict_csv(csv_data)?;
+        let builder = DictionaryBuilder::new(dictionary.metadata.clone());
+        match UserDictionaryLoader::load_from_csv_data(builder, csv_data.as_bytes()) {
+            Ok(ud) => Some(ud),
+            Err(e) => return Err(format!("Error: Failed to build user dictionary: {}", e)),
+        }
+    } else {
+        None
+    };
+
+    let segmenter = Segmenter::new(Mode::Normal, dictionary, user_dictionary);
+    Ok(Tokenizer::new(segmenter))
+}
+
+/// Analyzes `input`, which starts at byte `base` of the caller's text;
+/// returned offsets include `base`.
+pub(crate) fn analyze_text(tokenizer: &Tokenizer, input: &str, base: usize, params: &InputParams) -> Result<Vec<TokenInfo>, String> {
+    let (text, offset_map) = if params.normalize_halfwidth {
+        let (normalized, offsets) = normalize_kana_offsets(input);
+        (normalized, Some(offsets))
+    } else {
+        (input.to_string(), None)
+    };
+    let normalized_params;
+    let params = match (offset_map.as_deref(), params.suppress_ranges.as_ref()) {
+        (Some(offsets), Some(ranges)) => {
+            normalized_params = InputParams {
+                suppress_ranges: Some(ranges.iter().map(|&(start, end)| (normalized_offset(offsets, start), normalized_offset(offsets, end))).collect()),
+                ..params.clone()
+            };
+            &normalized_params
+        }
+        _ => params,
+    };
+
+    let mut result_list = dictionary_tokens(tokenizer, &text, params)?;
+
+    if params.split_number_counter {
+        result_list = split_number_counters(result_list);
+    }
+
+    if params.split_on_nakaguro {
+        result_list = result_list.into_iter().flat_map(split_on_nakaguro).collect();
+    }
+
+    if params.strip_inline_reading {
+        result_list = strip_inline_readings(result_list, &text);
+    }
+
+    if let Some(csv_data) = params.user_dict_csv.as_deref().filter(|_| params.merge_user_dict_splits) {
+        result_list = merge_user_dict_splits(result_list, &user_dict_entries(csv_data), params);
+    }
+
+    if params.canonicalize_readings {
+        canonicalize_readings(&mut result_list);
+    }
+
+    if params.redact_readings {
+        redact_readings(&mut result_list);
+    }
+
+    if params.search_keys || params.glossary_fields {
+        add_search_fields(&mut result_list, params);
+    }
+
+    map_offsets(&mut result_list, offset_map.as_deref(), base);
+
+    if params.distribute_group_ruby {
+        distribute_group_ruby(&mut result_list);
+    }
+
+    if let Some(marker) = &params.mark_okurigana {
+        mark_okurigana(&mut result_list, marker);
+    }
+
+    if params.output_kana != "katakana" || params.include_raw_reading || params.kana_by_pos.is_some() {
+        convert_output_kana(&mut result_list, params);
+    }
+
+    if params.token_level_ruby {
+        add_token_level_ruby(&mut result_list);
+    }
+
+    if params.inherit_pos {
+        inherit_pos(&mut result_list);
+    }
+
+    if params.include_ids {
+        for token in result_list.iter_mut() {
+            token.id = Some(token_id(&token.surface, token.byte_start));
+        }
+    }
+
+    if params.details_map {
+        for token in result_list.iter_mut() {
+            token.details_as_map();
+        }
+    }
+
+    if params.grapheme_count {
+        for token in result_list.iter_mut() {
+            token.grapheme_count = Some(grapheme_count(&token.surface));
+        }
+    }
+
+    if params.spacing_hints {
+        add_spacing_hints(&mut result_list);
+    }
+
+    fill_empty_segments(&mut result_list);
+
+    if params.reverse {
+        result_list.reverse();
+    }
+
+    Ok(result_list)
+}
+
+/// What the reading heuristics remember about the tokens before the current
+/// one. Cleared at every gap in the text.
+#[derive(Default)]
+struct HeuristicState {
+    prev_proper_noun: bool,
+    prev_number: Option<u32>,
+    numeral_run: String,
+    numeral_tokens: usize,
+    /// Whether the current token follows a kanji numeral.
+    follows_numeral: bool,
+    prev_numeral: Option<char>,
+    prev_kanji_reading: Option<String>,
+}
+
+impl HeuristicState {
+    /// Replaces the dictionary reading where a heuristic knows better: place
+    /// name suffixes, dates, numeral counters and context rules. Earlier
+    /// tokens may get their number reading rewritten as well.
+    fn adjust_reading(
+        &mut self,
+        entry: &mut TokenInfo,
+        reading: &mut String,
+        confidence: &mut Option<f32>,
+        result_list: &mut Vec<TokenInfo>,
+        params: &InputParams,
+    ) {
+        let surface = entry.surface.clone();
+        if params.place_name_readings && self.prev_proper_noun {
+            if let Some(suffix_reading) = place_suffix_reading(&surface) {
+                *reading = suffix_reading.to_string();
+                entry.heuristic_reading(reading);
+                *confidence = Some(MEDIUM_CONFIDENCE);
+            }
+        }
+        self.prev_proper_noun = entry.pos().1 == "固有名詞";
+
+        if params.datetime_readings {
+            if let Some((number_ruby, unit_ruby)) = self.prev_number.and_then(|n| datetime_reading(n, &surface)) {
+                *reading = unit_ruby.to_string();
+                entry.heuristic_reading(reading);
+                if let Some(number_ruby) = number_ruby {
+                    let count = self.numeral_tokens.max(1);
+                    let first = result_list.len().saturating_sub(count);
+                    if result_list.get(first).is_some_and(|token| !is_suppressed(token.byte_start, params)) {
+                        set_number_ruby(result_list, count, number_ruby);
+                    }
+                }
+            }
+        }
+        self.follows_numeral = self.numeral_tokens > 0;
+        if surface.chars().all(is_kanji_numeral) {
+            self.numeral_run.push_str(&surface);
+            self.numeral_tokens += 1;
+        } else {
+            self.numeral_run.clear();
+            self.numeral_tokens = 0;
+        }
+        self.prev_number = parse_number(&surface).or_else(|| parse_kanji_number(&self.numeral_run));
+
+        if params.counter_readings {
+            if let Some((numeral_ruby, counter_ruby)) = self.prev_numeral.and_then(|n| counter_reading(n, &surface)) {
+                *reading = counter_ruby.to_string();
+                entry.heuristic_reading(reading);
+                if let Some(prev) = result_list.last_mut().filter(|prev| !is_suppressed(prev.byte_start, params)) {
+                    prev.ruby_segments = vec![RubySegment {
+                        text: prev.surface.clone(),
+                        ruby: numeral_ruby.to_string(),
+                        ..Default::default()
+                    }];
+                }
+            }
+        }
+        self.prev_numeral = single_numeral(&surface);
+
+        if params.context_disambiguation {
+            if let Some(context) = context_reading(&surface, result_list.last().map(|t| t.surface.as_str())) {
+                if reading != context {
+                    *reading = context.to_string();
+                    entry.heuristic_reading(reading);
+                    *confidence = Some(MEDIUM_CONFIDENCE);
+                }
+            }
+        }
+    }
+
+    /// The token's ruby segments: iteration marks, counters and dates first,
+    /// then the regular alignment, grouped when its confidence is below
+    /// `group_ruby_below_confidence` and cleared for `known_words`.
+    fn ruby_segments(
+        &mut self,
+        entry: &TokenInfo,
+        reading: &str,
+        byte_start: usize,
+        confidence: &mut Option<f32>,
+        segment_cache: &mut HashMap<(String, String), Vec<RubySegment>>,
+        params: &InputParams,
+    ) -> Vec<RubySegment> {
+        let surface = &entry.surface;
+        let ruby_segments = match self.prev_kanji_reading.as_deref() {
+            Some(prev) if surface.starts_with('々') && !is_suppressed(byte_start, params) => {
+                iteration_mark_segments(surface, reading, prev, params)
+            }
+            _ => counter_segments(surface)
+                .filter(|_| params.counter_readings)
+                .or_else(|| datetime_segments(surface).filter(|_| params.datetime_readings && !self.follows_numeral))
+                .filter(|_| !is_suppressed(byte_start, params))
+                .unwrap_or_else(|| cached_ruby_segments(segment_cache, surface, reading, byte_start, params)),
+        };
+        let mut ruby_segments = match params.group_ruby_below_confidence {
+            Some(threshold) if surface.chars().any(is_kanji) && reading != "*" && !is_suppressed(byte_start, params) => {
+                let score = alignment_confidence(&ruby_segments, *confidence);
+                if score < threshold {
+                    *confidence = Some(score);
+                    vec![RubySegment {
+                        text: surface.clone(),
+                        ruby: reading.to_string(),
+                        ..Default::default()
+                    }]
+                } else {
+                    ruby_segments
+                }
+            }
+            _ => ruby_segments,
+        };
+        if params.known_words.as_ref().is_some_and(|known| known.contains(surface) || entry.base_form().is_some_and(|base| known.iter().any(|word| word == base))) {
+            for seg in ruby_segments.iter_mut() {
+                seg.ruby.clear();
+            }
+        }
+        self.prev_kanji_reading = trailing_kanji_reading(&ruby_segments);
+        ruby_segments
+    }
+}
+
+/// Tokenizes `text` and turns every token into a `TokenInfo` with its reading
+/// and ruby segments. Untokenized gaps become whitespace tokens.
+fn dictionary_tokens(tokenizer: &Tokenizer, text: &str, params: &InputParams) -> Result<Vec<TokenInfo>, String> {
+    let mut tokens = match tokenizer.tokenize(text) {
+        Ok(t) => t,
+        Err(e) => return Err(format!("Error: Tokenization failed: {}", e)),
+    };
+
+    let mut result_list: Vec<TokenInfo> = Vec::new();
+    let mut cursor_byte = 0;
+    let text_bytes = text.as_bytes();
+
+    let mut state = HeuristicState::default();
+    let mut segment_cache = HashMap::new();
+    let mut fallbacks = Vec::new();
+
+    for token in tokens.iter_mut() {
+        // An empty token carries no text; skipping it leaves `cursor_byte`
+        // where it was, so the next gap is still measured from real text.
+        if token.byte_start >= token.byte_end {
+            continue;
+        }
+        if token.byte_start > cursor_byte {
+            let gap_slice = &text_bytes[cursor_byte..token.byte_start];
+            let gap_text = String::from_utf8_lossy(gap_slice).to_string();
+            push_gap_tokens(&mut result_list, &gap_text, cursor_byte);
+            state = HeuristicState::default();
+        }
+
+        let surface = token.surface.to_string();
+        // The dictionary returns a run of full-width spaces as one token;
+        // split it like a gap so each space is its own `ideographic_space`.
+        if surface.chars().all(|c| c == '\u{3000}') {
+            push_gap_tokens(&mut result_list, &surface, token.byte_start);
+            state = HeuristicState::default();
+            cursor_byte = token.byte_end;
+            continue;
+        }
+        let kind = token_kind(&surface);
+        let details: Vec<String> = token.details().iter().map(|s| s.to_string()).collect();
+        let (mut entry, mut reading) = TokenInfo::from_entry(&surface, details, params);
+        let mut confidence = None;
+        state.adjust_reading(&mut entry, &mut reading, &mut confidence, &mut result_list, params);
+
+        let warnings = (params.reading_warnings && contains_kanji(&reading)).then(|| {
+            vec![format!("Reading {:?} contains kanji; the token was left without ruby", reading)]
+        });
+
+        if params.strict
+            && surface.chars().any(is_kanji)
+            && !is_suppressed(token.byte_start, params)
+            && !is_clean_alignment(&surface, &reading, params)
+        {
+            fallbacks.push(serde_json::json!({ "surface": surface, "reading": &reading, "byte_start": token.byte_start }));
+        }
+
+        let lattice = if params.debug_lattice && kind == "word" {
+            Some(lattice_nodes(token.dictionary, &text[token.byte_start..]))
+        } else {
+            None
+        };
+
+        let full_reading = if params.include_full_reading && kind == "word" {
+            Some(if reading == "*" { String::new() } else { reading.clone() })
+        } else {
+            None
+        };
+
+        let alternatives = if params.include_alternatives && kind == "word" && token.word_id.is_system {
+            Some(reading_alternatives(token.dictionary, &surface, &reading, params.max_alternatives))
+        } else {
+            None
+        };
+
+        let ruby_segments = state.ruby_segments(&entry, &reading, token.byte_start, &mut confidence, &mut segment_cache, params);
+
+        result_list.push(TokenInfo {
+            ruby_segments,
+            confidence,
+            full_reading,
+            word_id: (params.include_word_ids && !token.word_id.is_unknown()).then_some(EntryId {
+                id: token.word_id.id,
+                system: token.word_id.is_system,
+            }),
+            alternatives,
+            warnings,
+            lattice,
+            kind,
+            byte_start: token.byte_start,
+            byte_end: token.byte_end,
+            ..entry
+        });
+
+        cursor_byte = token.byte_end;
+    }
+
+    if !fallbacks.is_empty() {
+        return Err(format!(
+            "Error: Strict mode: alignment fell back for {} token(s): {}",
+            fallbacks.len(),
+            serde_json::Value::from(fallbacks)
+        ));
+    }
+
+    if cursor_byte < text_bytes.len() {
+        let gap_slice = &text_bytes[cursor_byte..];
+        let gap_text = String::from_utf8_lossy(gap_slice).to_string();
+        push_gap_tokens(&mut result_list, &gap_text, cursor_byte);
+    }
+
+    Ok(result_list)
+}
+
+/// Splits every numeral+counter token in two (`split_number_counter`).
+fn split_number_counters(result_list: Vec<TokenInfo>) -> Vec<TokenInfo> {
+    let mut split_list = Vec::with_capacity(result_list.len());
+    for token in result_list {
+        match split_number_counter(&token) {
+            Some((numeral_token, counter_token)) => {
+                split_list.push(numeral_token);
+                split_list.push(counter_token);
+            }
+            None => split_list.push(token),
+        }
+    }
+    split_list
+}
+
+/// Rewrites readings to their canonical spelling (`canonicalize_readings`).
+fn canonicalize_readings(result_list: &mut [TokenInfo]) {
+    for token in result_list.iter_mut() {
+        for seg in token.ruby_segments.iter_mut().filter(|seg| !seg.ruby.is_empty()) {
+            seg.ruby = canonical_reading(&seg.ruby);
+        }
+        if let Some(full_reading) = token.full_reading.as_mut() {
+            *full_reading = canonical_reading(full_reading);
+        }
+    }
+}
+
+/// Masks every reading with ● per mora (`redact_readings`).
+fn redact_readings(result_list: &mut [TokenInfo]) {
+    for token in result_list.iter_mut() {
+        for seg in token.ruby_segments.iter_mut().filter(|seg| !seg.ruby.is_empty()) {
+            seg.ruby = redact_reading(&seg.ruby);
+        }
+        for reading in token.full_reading.iter_mut().chain(token.alternatives.iter_mut().flatten()) {
+            *reading = redact_reading(reading);
+        }
+        for node in token.lattice.iter_mut().flatten() {
+            node.reading = redact_reading(&node.reading);
+        }
+    }
+}
+
+/// Adds `search_key` (`search_keys`) and `word`/`reading_hira`
+/// (`glossary_fields`) to tokens containing kanji.
+fn add_search_fields(result_list: &mut [TokenInfo], params: &InputParams) {
+    for token in result_list.iter_mut().filter(|token| token.surface.chars().any(is_kanji)) {
+        let reading = hiragana_reading(&token.ruby_segments);
+        if params.search_keys {
+            token.search_key = Some(format!("{} {}", token.surface, reading));
+        }
+        // A token without a reading has no glossary row.
+        if params.glossary_fields && !reading.chars().any(is_kanji) {
+            token.word = Some(token.surface.clone());
+            token.reading_hira = Some(reading);
+        }
+    }
+}
+
+/// The first byte of the normalized text that comes from original byte
+/// `offset` or later, so caller ranges can be checked against normalized tokens.
+fn normalized_offset(offsets: &[usize], offset: usize) -> usize {
+    offsets.partition_point(|&original| original < offset)
+}
+
+/// Moves token offsets from the analyzed text back to the caller's: through
+/// the normalization offset map, if any, then by `base`.
+fn map_offsets(result_list: &mut [TokenInfo], offset_map: Option<&[usize]>, base: usize) {
+    if let Some(offsets) = offset_map {
+        for token in result_list.iter_mut() {
+            token.byte_start = offsets[token.byte_start];
+            token.byte_end = offsets[token.byte_end];
+        }
+    }
+
+    if base > 0 {
+        for token in result_list.iter_mut() {
+            token.byte_start += base;
+            token.byte_end += base;
+        }
+    }
+}
+
+/// Renderers rely on every token carrying at least one segment that spells
+/// its surface, so a token left without any gets a ruby-less one.
+pub(crate) fn fill_empty_segments(result_list: &mut [TokenInfo]) {
+    for token in result_list.iter_mut().filter(|token| token.ruby_segments.is_empty()) {
+        token.ruby_segments.push(RubySegment {
+            text: token.surface.clone(),
+            ruby: "".to_string(),
+            ..Default::default()
+        });
+    }
+}
+
+/// Gives group ruby a per-character `distribution` (`distribute_group_ruby`).
+fn distribute_group_ruby(result_list: &mut [TokenInfo]) {
+    for token in result_list.iter_mut() {
+        for seg in token.ruby_segments.iter_mut().filter(|seg| !seg.ruby.is_empty() && seg.text.chars().nth(1).is_some()) {
+            seg.distribution = Some(distribute_morae(&seg.text, &seg.ruby));
+        }
+    }
+}
+
+/// Sets `full_reading` to the reading with `marker` at every boundary between
+/// ruby and bare text (`mark_okurigana`).
+fn mark_okurigana(result_list: &mut [TokenInfo], marker: &str) {
+    for token in result_list.iter_mut().filter(|token| token.ruby_segments.iter().any(|seg| !seg.ruby.is_empty())) {
+        let mut marked = String::new();
+        let mut prev_has_ruby = None;
+        for seg in &token.ruby_segments {
+            let has_ruby = !seg.ruby.is_empty();
+            if prev_has_ruby.is_some_and(|prev| prev != has_ruby) {
+                marked.push_str(marker);
+            }
+            prev_has_ruby = Some(has_ruby);
+            if has_ruby {
+                marked.push_str(&seg.ruby);
+            } else {
+                marked.extend(seg.text.chars().map(hira_to_kata));
+            }
+        }
+        token.full_reading = Some(marked);
+    }
+}
+
+/// Converts every reading to the token's output script (`output_kana`,
+/// `kana_by_pos`), keeping the katakana in `raw_ruby` when asked.
+fn convert_output_kana(result_list: &mut [TokenInfo], params: &InputParams) {
+    for token in result_list.iter_mut() {
+        let (pos, sub_pos) = token.pos();
+        let output_kana = output_kana_for(pos, sub_pos, params);
+        for seg in token.ruby_segments.iter_mut().filter(|seg| !seg.ruby.is_empty()) {
+            if params.include_raw_reading {
+                seg.raw_ruby = Some(seg.ruby.clone());
+            }
+            seg.ruby = convert_reading(&seg.ruby, output_kana);
+            for part in seg.distribution.iter_mut().flatten() {
+                *part = convert_reading(part, output_kana);
+            }
+        }
+        if let Some(full_reading) = token.full_reading.as_mut() {
+            *full_reading = convert_reading(full_reading, output_kana);
+        }
+        for alternative in token.alternatives.iter_mut().flatten() {
+            *alternative = convert_reading(alternative, output_kana);
+        }
+    }
+}
+
+/// Adds the token-level `base` and `rt` (`token_level_ruby`).
+fn add_token_level_ruby(result_list: &mut [TokenInfo]) {
+    for token in result_list.iter_mut() {
+        let rt = if token.ruby_segments.iter().all(|seg| seg.ruby.is_empty()) {
+            String::new()
+        } else {
+            token
+                .ruby_segments
+                .iter()
+                .map(|seg| if seg.ruby.is_empty() { seg.text.as_str() } else { seg.ruby.as_str() })
+                .collect()
+        };
+        token.base = Some(token.surface.clone());
+        token.rt = Some(rt);
+    }
+}
+
+/// Copies each word's part of speech onto its segments (`inherit_pos`).
+fn inherit_pos(result_list: &mut [TokenInfo]) {
+    for token in result_list.iter_mut().filter(|token| token.kind == "word") {
+        let pos = Some(token.pos().0.to_string());
+        for seg in token.ruby_segments.iter_mut() {
+            seg.pos = pos.clone();
+        }
+    }
+}
+
+/// Sets `space_before` from the characters on either side of each token
+/// boundary (`spacing_hints`).
+fn add_spacing_hints(result_list: &mut [TokenInfo]) {
+    let mut prev_last: Option<char> = None;
+    for token in result_list.iter_mut() {
+        let first = token.surface.chars().next();
+        token.space_before = Some(match (prev_last, first) {
+            (Some(prev), Some(next)) => space_between(prev, next),
+            _ => "none",
+        });
+        prev_last = token.surface.chars().last();
+    }
+}
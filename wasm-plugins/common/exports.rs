@@ -0,0 +1,328 @@
+//! The `#[wasm_func]` entry points, shared by every dictionary plugin.
+
+use super::*;
+
+#[wasm_func]
+pub fn analyze(input_bytes: &[u8]) -> Vec<u8> {
+    let params = match parse_params(input_bytes) {
+        Ok(p) => p,
+        Err(e) => return e.into_bytes(),
+    };
+    let result_list = match analyze_tokens(&params) {
+        Ok(list) => list,
+        Err(e) => return e.into_bytes(),
+    };
+
+    if params.coverage {
+        return serde_json::Value::from(reading_coverage(&result_list)).to_string().into_bytes();
+    }
+
+    let meta = params.include_meta.then(|| result_meta(&params));
+    match (params.output.as_deref(), meta) {
+        (Some("mecab"), _) => mecab_lines(&result_list),
+        (Some("aozora"), _) => aozora_text(&result_list),
+        (Some("compact"), _) => compact_records(&result_list),
+        (Some("jsonl"), Some(meta)) => {
+            let mut bytes = serde_json::json!({ "meta": meta }).to_string().into_bytes();
+            bytes.push(b'\n');
+            bytes.extend(jsonl_lines(&result_list));
+            bytes
+        }
+        (Some("jsonl"), None) => jsonl_lines(&result_list),
+        (_, Some(meta)) => {
+            let mut bytes = format!("{{\"meta\":{},\"tokens\":", meta).into_bytes();
+            bytes.extend(serialize_tokens(&result_list));
+            bytes.push(b'}');
+            bytes
+        }
+        (_, None) => serialize_tokens(&result_list),
+    }
+}
+
+/// Returns the reading of the whole text as a plain string, using each
+/// token's ruby where present and its surface otherwise. The particles は,
+/// へ and を are written as pronounced (ワ, エ, オ).
+#[wasm_func]
+pub fn reading_only(input_bytes: &[u8]) -> Vec<u8> {
+    let params = match parse_params(input_bytes) {
+        Ok(p) => p,
+        Err(e) => return e.into_bytes(),
+    };
+    let result_list = match analyze_tokens(&params) {
+        Ok(list) => list,
+        Err(e) => return e.into_bytes(),
+    };
+
+    let readings: Vec<String> = result_list
+        .iter()
+        .map(|token| {
+            let (pos, sub_pos) = token.pos();
+            if let Some(spoken) = particle_reading(&token.surface).filter(|_| pos == "助詞") {
+                return convert_reading(spoken, output_kana_for(pos, sub_pos, &params));
+            }
+            token
+                .ruby_segments
+                .iter()
+                .map(|seg| if seg.ruby.is_empty() { seg.text.as_str() } else { seg.ruby.as_str() })
+                .collect()
+        })
+        .collect();
+
+    readings.join(params.reading_separator.as_deref().unwrap_or("")).into_bytes()
+}
+
+#[derive(Deserialize)]
+struct WordsParams {
+    words: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct WordReading {
+    word: String,
+    reading: String,
+    ruby_segments: Vec<RubySegment>,
+}
+
+/// Annotates a list of headwords, each analyzed on its own with a shared
+/// tokenizer. Accepts the `analyze` options alongside `words`.
+#[wasm_func]
+pub fn readings_for_words(input_bytes: &[u8]) -> Vec<u8> {
+    let mut value: serde_json::Value = match serde_json::from_slice(input_bytes) {
+        Ok(v) => v,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    let words: WordsParams = match serde_json::from_value(value.clone()) {
+        Ok(w) => w,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    if let Some(object) = value.as_object_mut() {
+        object.entry("text").or_insert_with(|| "".into());
+    }
+    let params: InputParams = match serde_json::from_value(value) {
+        Ok(p) => p,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    let tokenizer = match build_tokenizer(&params) {
+        Ok(t) => t,
+        Err(e) => return e.into_bytes(),
+    };
+
+    let mut results = Vec::with_capacity(words.words.len());
+    for word in words.words {
+        let tokens = match analyze_text(&tokenizer, &word, 0, &params) {
+            Ok(list) => list,
+            Err(e) => return e.into_bytes(),
+        };
+        let ruby_segments: Vec<RubySegment> = tokens.into_iter().flat_map(|token| token.ruby_segments).collect();
+        // Bare kana is converted like the ruby so the reading uses one script.
+        let reading = ruby_segments
+            .iter()
+            .map(|seg| {
+                if seg.ruby.is_empty() {
+                    let kana: String = seg.text.chars().map(hira_to_kata).collect();
+                    convert_reading(&kana, &params.output_kana)
+                } else {
+                    seg.ruby.clone()
+                }
+            })
+            .collect();
+        results.push(WordReading { word, reading, ruby_segments });
+    }
+
+    match serde_json::to_vec(&results) {
+        Ok(bytes) => bytes,
+        Err(e) => format!("Error: Serialization failed: {}", e).into_bytes(),
+    }
+}
+
+#[derive(Deserialize)]
+struct StripParams {
+    text: String,
+    /// Annotation styles to remove: "paren" (漢字（かんじ）) and "aozora"
+    /// (｜漢字《かんじ》); both by default.
+    #[serde(default = "default_strip_styles")]
+    styles: Vec<String>,
+}
+
+fn default_strip_styles() -> Vec<String> {
+    vec!["paren".to_string(), "aozora".to_string()]
+}
+
+/// Removes inline ruby from `text` and returns the bare text, the inverse of
+/// writing readings inline: `paren` drops the parenthetical of 漢字（かんじ）
+/// as `strip_inline_reading` recognizes it, `aozora` the ｜ and 《》 markup.
+#[wasm_func]
+pub fn strip_ruby(input_bytes: &[u8]) -> Vec<u8> {
+    let params: StripParams = match serde_json::from_slice(input_bytes) {
+        Ok(p) => p,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    if params.styles.iter().any(|style| !["paren", "aozora"].contains(&style.as_str())) {
+        return "Error: styles must be one of: paren, aozora".to_string().into_bytes();
+    }
+
+    let text = params.text;
+    let mut ranges = Vec::new();
+    if params.styles.iter().any(|style| style == "paren") {
+        ranges.extend(inline_readings(&text).into_iter().map(|inline| inline.paren));
+    }
+    if params.styles.iter().any(|style| style == "aozora") {
+        ranges.extend(aozora_markup(&text));
+    }
+    ranges.sort();
+
+    let mut out = String::with_capacity(text.len());
+    let mut cursor = 0;
+    for (start, end) in ranges {
+        // Overlapping markup (a parenthetical inside 《》) was already skipped.
+        if start < cursor {
+            cursor = cursor.max(end);
+            continue;
+        }
+        out.push_str(&text[cursor..start]);
+        cursor = end;
+    }
+    out.push_str(&text[cursor..]);
+    out.into_bytes()
+}
+
+#[derive(Deserialize)]
+struct Span {
+    text: String,
+    reading: String,
+}
+
+#[derive(Deserialize)]
+struct SpansParams {
+    spans: Vec<Span>,
+}
+
+#[derive(Serialize)]
+struct SpanRuby {
+    text: String,
+    ruby_segments: Vec<RubySegment>,
+}
+
+/// Aligns readings supplied by an upstream tool: each span is one `text`
+/// with its whole `reading` (hiragana or katakana), which may cover several
+/// kanji runs (読み書き よみかき) and is divided by the regular alignment
+/// without tokenizing. Accepts the `analyze` options alongside `spans`;
+/// `suppress_ranges` count bytes of the spans' texts joined together.
+#[wasm_func]
+pub fn build_ruby_for_spans(input_bytes: &[u8]) -> Vec<u8> {
+    let mut value: serde_json::Value = match serde_json::from_slice(input_bytes) {
+        Ok(v) => v,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    let spans: SpansParams = match serde_json::from_value(value.clone()) {
+        Ok(s) => s,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    if let Some(object) = value.as_object_mut() {
+        object.entry("text").or_insert_with(|| "".into());
+    }
+    let params: InputParams = match serde_json::from_value(value) {
+        Ok(p) => p,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    if let Err(e) = validate_params(&params) {
+        return e.into_bytes();
+    }
+
+    let mut results = Vec::with_capacity(spans.spans.len());
+    let mut byte_start = 0;
+    for span in spans.spans {
+        let reading: String = normalize_kana(&span.reading).chars().map(hira_to_kata).collect();
+        let mut ruby_segments = token_ruby_segments(&span.text, &reading, byte_start, &params);
+        for seg in ruby_segments.iter_mut().filter(|seg| !seg.ruby.is_empty()) {
+            seg.ruby = convert_reading(&seg.ruby, &params.output_kana);
+        }
+        byte_start += span.text.len();
+        results.push(SpanRuby { text: span.text, ruby_segments });
+    }
+
+    match serde_json::to_vec(&results) {
+        Ok(bytes) => bytes,
+        Err(e) => format!("Error: Serialization failed: {}", e).into_bytes(),
+    }
+}
+
+#[derive(Deserialize)]
+struct ReadingParams {
+    reading: String,
+}
+
+#[derive(Serialize)]
+struct SurfaceCandidate {
+    surface: String,
+    cost: i16,
+    pos: String,
+}
+
+/// Number of entries in the system dictionary. Lindera keeps one 4-byte
+/// offset per entry in `words_idx_data` and exposes no count of its own.
+fn system_word_count(dictionary: &Dictionary) -> usize {
+    dictionary.prefix_dictionary.words_idx_data.len() / 4
+}
+
+/// Reverse lookup: dictionary surfaces read as `reading`, cheapest first.
+/// Entries do not store their surface, so the `LOOKUP_SURFACE_INDEX` column
+/// stands in for it and is confirmed through the prefix dictionary, which
+/// also gives the cost. Entries whose column differs from their surface
+/// (inflected forms under a base form) are skipped.
+#[wasm_func]
+pub fn surfaces_for_reading(input_bytes: &[u8]) -> Vec<u8> {
+    let params: ReadingParams = match serde_json::from_slice(input_bytes) {
+        Ok(p) => p,
+        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
+    };
+    let reading: String = normalize_kana(&params.reading).chars().map(hira_to_kata).collect();
+    let dictionary = get_dictionary();
+
+    let mut candidates: Vec<SurfaceCandidate> = Vec::new();
+    for id in 0..system_word_count(dictionary) {
+        let details = dictionary.word_details(id);
+        if details.get(READING_INDEX) != Some(&reading.as_str()) {
+            continue;
+        }
+        let Some(surface) = details.get(LOOKUP_SURFACE_INDEX).filter(|s| **s != "*") else {
+            continue;
+        };
+        // Confirms the candidate really is this entry's surface.
+        let Some(cost) = dictionary
+            .prefix_dictionary
+            .prefix(surface)
+            .filter(|(len, entry)| *len == surface.len() && entry.word_id.id as usize == id)
+            .map(|(_, entry)| entry.word_cost)
+            .min()
+        else {
+            continue;
+        };
+        match candidates.iter_mut().find(|c| c.surface == *surface) {
+            Some(existing) if existing.cost <= cost => {}
+            Some(existing) => {
+                existing.cost = cost;
+                existing.pos = details[0].to_string();
+            }
+            None => candidates.push(SurfaceCandidate {
+                surface: surface.to_string(),
+                cost,
+                pos: details[0].to_string(),
+            }),
+        }
+    }
+    candidates.sort_by(|a, b| a.cost.cmp(&b.cost).then_with(|| a.surface.cmp(&b.surface)));
+
+    match serde_json::to_vec(&candidates) {
+        Ok(bytes) => bytes,
+        Err(e) => format!("Error: Serialization failed: {}", e).into_bytes(),
+    }
+}
+
+#[wasm_func]
+pub fn detail_schema() -> Vec<u8> {
+    match serde_json::to_vec(&DETAIL_SCHEMA) {
+        Ok(bytes) => bytes,
+        Err(e) => format!("Error: Serialization failed: {}", e).into_bytes(),
+    }
+}
@@ -0,0 +1,460 @@
+//! Kana, romaji and character class helpers.
+
+use super::*;
+
+pub(crate) fn hira_to_kata(c: char) -> char {
+    if c >= '\u{3041}' && c <= '\u{3096}' {
+        std::char::from_u32(c as u32 + 0x60).unwrap()
+    } else {
+        c
+    }
+}
+
+pub(crate) fn is_hiragana(c: char) -> bool {
+    c >= '\u{3040}' && c <= '\u{309F}'
+}
+
+pub(crate) fn is_kanji(c: char) -> bool {
+    (c >= '\u{4E00}' && c <= '\u{9FFF}') ||
+    (c >= '\u{3400}' && c <= '\u{4DBF}') ||
+    (c >= '\u{20000}' && c <= '\u{2A6DF}')
+}
+
+pub(crate) fn contains_kanji(s: &str) -> bool {
+    s.chars().any(is_kanji)
+}
+
+pub(crate) fn is_katakana(c: char) -> bool {
+    c >= '\u{30A0}' && c <= '\u{30FF}'
+}
+
+pub(crate) fn kata_to_hira(c: char) -> char {
+    if c >= '\u{30A1}' && c <= '\u{30F6}' {
+        std::char::from_u32(c as u32 - 0x60).unwrap()
+    } else {
+        c
+    }
+}
+
+pub(crate) fn kana_romaji(c: char) -> Option<&'static str> {
+    let romaji = match c {
+        'ア' => "a", 'イ' => "i", 'ウ' => "u", 'エ' => "e", 'オ' => "o",
+        'カ' => "ka", 'キ' => "ki", 'ク' => "ku", 'ケ' => "ke", 'コ' => "ko",
+        'ガ' => "ga", 'ギ' => "gi", 'グ' => "gu", 'ゲ' => "ge", 'ゴ' => "go",
+        'サ' => "sa", 'シ' => "shi", 'ス' => "su", 'セ' => "se", 'ソ' => "so",
+        'ザ' => "za", 'ジ' => "ji", 'ズ' => "zu", 'ゼ' => "ze", 'ゾ' => "zo",
+        'タ' => "ta", 'チ' => "chi", 'ツ' => "tsu", 'テ' => "te", 'ト' => "to",
+        'ダ' => "da", 'ヂ' => "ji", 'ヅ' => "zu", 'デ' => "de", 'ド' => "do",
+        'ナ' => "na", 'ニ' => "ni", 'ヌ' => "nu", 'ネ' => "ne", 'ノ' => "no",
+        'ハ' => "ha", 'ヒ' => "hi", 'フ' => "fu", 'ヘ' => "he", 'ホ' => "ho",
+        'バ' => "ba", 'ビ' => "bi", 'ブ' => "bu", 'ベ' => "be", 'ボ' => "bo",
+        'パ' => "pa", 'ピ' => "pi", 'プ' => "pu", 'ペ' => "pe", 'ポ' => "po",
+        'マ' => "ma", 'ミ' => "mi", 'ム' => "mu", 'メ' => "me", 'モ' => "mo",
+        'ヤ' => "ya", 'ユ' => "yu", 'ヨ' => "yo",
+        'ラ' => "ra", 'リ' => "ri", 'ル' => "ru", 'レ' => "re", 'ロ' => "ro",
+        'ワ' => "wa", 'ヰ' => "i", 'ヱ' => "e", 'ヲ' => "o", 'ン' => "n", 'ヴ' => "vu",
+        'ァ' => "a", 'ィ' => "i", 'ゥ' => "u", 'ェ' => "e", 'ォ' => "o",
+        'ャ' => "ya", 'ュ' => "yu", 'ョ' => "yo", 'ヮ' => "wa",
+        _ => return None,
+    };
+    Some(romaji)
+}
+
+/// Converts a katakana reading to Hepburn romaji.
+/// Digraphs (キャ kya, シェ she), ッ (gemination), ン before a vowel (n')
+/// and ー or half-width ｰ (repeats the previous vowel; dropped when nothing
+/// precedes it) are handled; unknown chars pass through.
+pub(crate) fn kata_to_romaji(reading: &str) -> String {
+    let chars: Vec<char> = reading.chars().map(hira_to_kata).collect();
+    let mut out = String::new();
+    let mut geminate = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            'ッ' => {
+                geminate = true;
+                i += 1;
+                continue;
+            }
+            'ー' | '\u{FF70}' => {
+                if let Some(vowel) = out.chars().last().filter(|v| "aiueo".contains(*v)) {
+                    out.push(vowel);
+                }
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let Some(base) = kana_romaji(c) else {
+            out.push(c);
+            geminate = false;
+            i += 1;
+            continue;
+        };
+
+        let mut syllable = base.to_string();
+        if let Some(&next) = chars.get(i + 1) {
+            match next {
+                'ャ' | 'ュ' | 'ョ' if base.len() > 1 => {
+                    let glide = &kana_romaji(next).unwrap()[1..];
+                    syllable.pop();
+                    if !(syllable.ends_with("sh") || syllable.ends_with("ch") || syllable == "j") {
+                        syllable.push('y');
+                    }
+                    syllable.push_str(glide);
+                    i += 1;
+                }
+                'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ' => {
+                    let vowel = kana_romaji(next).unwrap();
+                    if syllable == "u" {
+                        syllable = "w".to_string();
+                    } else {
+                        syllable.pop();
+                    }
+                    syllable.push_str(vowel);
+                    i += 1;
+                }
+                _ => {}
+            }
+        }
+
+        if c == 'ン' && chars.get(i + 1).and_then(|&n| kana_romaji(n)).is_some_and(|r| r.starts_with(['a', 'i', 'u', 'e', 'o', 'y'])) {
+            syllable.push('\'');
+        }
+
+        if geminate {
+            if syllable.starts_with("ch") {
+                out.push('t');
+            } else if let Some(first) = syllable.chars().next().filter(|f| !"aiueon".contains(*f)) {
+                out.push(first);
+            }
+            geminate = false;
+        }
+
+        out.push_str(&syllable);
+        i += 1;
+    }
+    out
+}
+
+pub(crate) fn convert_reading(reading: &str, output_kana: &str) -> String {
+    match output_kana {
+        "hiragana" => reading.chars().map(kata_to_hira).collect(),
+        "romaji" => kata_to_romaji(reading),
+        _ => reading.to_string(),
+    }
+}
+
+pub(crate) fn halfwidth_to_fullwidth(c: char) -> char {
+    const SMALL: [char; 10] = ['ヲ', 'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ャ', 'ュ', 'ョ', 'ッ'];
+    const BASIC: [char; 45] = [
+        'ア', 'イ', 'ウ', 'エ', 'オ', 'カ', 'キ', 'ク', 'ケ', 'コ',
+        'サ', 'シ', 'ス', 'セ', 'ソ', 'タ', 'チ', 'ツ', 'テ', 'ト',
+        'ナ', 'ニ', 'ヌ', 'ネ', 'ノ', 'ハ', 'ヒ', 'フ', 'ヘ', 'ホ',
+        'マ', 'ミ', 'ム', 'メ', 'モ', 'ヤ', 'ユ', 'ヨ',
+        'ラ', 'リ', 'ル', 'レ', 'ロ', 'ワ', 'ン',
+    ];
+    match c {
+        // Half-width punctuation (｡｢｣､･) goes along so normalized text does
+        // not mix widths; it stays a symbol either way.
+        '\u{FF61}' => '。',
+        '\u{FF62}' => '「',
+        '\u{FF63}' => '」',
+        '\u{FF64}' => '、',
+        '\u{FF65}' => '・',
+        '\u{FF66}'..='\u{FF6F}' => SMALL[(c as u32 - 0xFF66) as usize],
+        '\u{FF70}' => 'ー',
+        '\u{FF71}'..='\u{FF9D}' => BASIC[(c as u32 - 0xFF71) as usize],
+        '\u{FF9E}' => '゛',
+        '\u{FF9F}' => '゜',
+        _ => c,
+    }
+}
+
+/// Combines a full-width katakana with a following half-width (ﾞ/ﾟ) or
+/// combining (U+3099/U+309A) voicing mark, e.g. ハ + ﾞ -> バ.
+pub(crate) fn compose_halfwidth_voicing(base: char, mark: char) -> Option<char> {
+    if ('\u{3041}'..='\u{3096}').contains(&base) {
+        return compose_halfwidth_voicing(hira_to_kata(base), mark).map(kata_to_hira);
+    }
+    let voiced = matches!(mark, '\u{FF9E}' | '\u{3099}');
+    let semi_voiced = matches!(mark, '\u{FF9F}' | '\u{309A}');
+    let offset = match base {
+        'カ' | 'キ' | 'ク' | 'ケ' | 'コ' | 'サ' | 'シ' | 'ス' | 'セ' | 'ソ' | 'タ' | 'チ' | 'ツ' | 'テ' | 'ト' if voiced => 1,
+        'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' if voiced => 1,
+        'ハ' | 'ヒ' | 'フ' | 'ヘ' | 'ホ' if semi_voiced => 2,
+        'ウ' if voiced => return Some('ヴ'),
+        _ => return None,
+    };
+    std::char::from_u32(base as u32 + offset)
+}
+
+/// Normalizes half-width katakana to full-width, composing voicing marks.
+pub(crate) fn normalize_kana(text: &str) -> String {
+    normalize_kana_offsets(text).0
+}
+
+/// `normalize_kana` that also returns, for every byte of the normalized text
+/// (plus its end), the byte offset of the original char it came from.
+/// Voicing marks are composed wherever they occur, not only word-initially.
+pub(crate) fn normalize_kana_offsets(text: &str) -> (String, Vec<usize>) {
+    let mut out = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    let mut chars = text.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        let full = halfwidth_to_fullwidth(c);
+        let normalized = match chars.peek().and_then(|&(_, mark)| compose_halfwidth_voicing(full, mark)) {
+            Some(composed) => {
+                chars.next();
+                composed
+            }
+            None => full,
+        };
+        out.push(normalized);
+        offsets.extend(std::iter::repeat_n(idx, normalized.len_utf8()));
+    }
+    offsets.push(text.len());
+    (out, offsets)
+}
+
+/// Full-width katakana form of a reading: half-width kana widened, voicing
+/// marks composed and hiragana converted (ｶﾞｯｺｳ, がっこう -> ガッコウ).
+pub(crate) fn canonical_reading(reading: &str) -> String {
+    normalize_kana(reading).chars().map(hira_to_kata).collect()
+}
+
+/// Maps a small katakana to its large counterpart (ッ -> ツ, ャ -> ヤ).
+pub(crate) fn kata_to_large(c: char) -> char {
+    match c {
+        'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ' | 'ッ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ' => {
+            std::char::from_u32(c as u32 + 1).unwrap()
+        }
+        'ヵ' => 'カ',
+        'ヶ' => 'ケ',
+        _ => c,
+    }
+}
+
+pub(crate) fn kana_eq(a: char, b: char, loose_size: bool) -> bool {
+    if loose_size {
+        kata_to_large(a) == kata_to_large(b)
+    } else {
+        a == b
+    }
+}
+
+/// Whether `c` joins the preceding character in a grapheme cluster:
+/// combining marks (including the kana voicing marks, full- and half-width),
+/// variation selectors, ZWJ, emoji modifiers and tag characters.
+pub(crate) fn extends_grapheme(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}' | '\u{1AB0}'..='\u{1AFF}' | '\u{1DC0}'..='\u{1DFF}' | '\u{20D0}'..='\u{20FF}'
+        | '\u{FE20}'..='\u{FE2F}' | '\u{3099}' | '\u{309A}' | '\u{FF9E}' | '\u{FF9F}'
+        | '\u{FE00}'..='\u{FE0F}' | '\u{E0100}'..='\u{E01EF}' | '\u{200D}'
+        | '\u{1F3FB}'..='\u{1F3FF}' | '\u{E0020}'..='\u{E007F}')
+}
+
+/// Number of grapheme clusters in `text`, following the extended cluster
+/// rules closely enough for Japanese text and emoji: extending characters
+/// join their base, a ZWJ also pulls in the next character (👨‍👩‍👧), and
+/// regional indicators pair into flags. Hangul jamo and Indic spacing marks
+/// are not joined.
+pub(crate) fn grapheme_count(text: &str) -> usize {
+    let is_regional = |c: char| ('\u{1F1E6}'..='\u{1F1FF}').contains(&c);
+    let mut count = 0;
+    let mut prev: Option<char> = None;
+    let mut open_flag = false;
+    for c in text.chars() {
+        let joins = match prev {
+            None => false,
+            Some('\u{200D}') => true,
+            Some(_) if extends_grapheme(c) => true,
+            Some(p) => open_flag && is_regional(p) && is_regional(c),
+        };
+        if !joins {
+            count += 1;
+        }
+        open_flag = is_regional(c) && !(joins && open_flag);
+        prev = Some(c);
+    }
+    count
+}
+
+/// ASCII form of a half- or full-width (U+FF21–FF5A) Latin letter.
+pub(crate) fn ascii_latin(c: char) -> Option<char> {
+    match c {
+        'A'..='Z' | 'a'..='z' => Some(c),
+        '\u{FF21}'..='\u{FF3A}' | '\u{FF41}'..='\u{FF5A}' => std::char::from_u32(c as u32 - 0xFEE0),
+        _ => None,
+    }
+}
+
+/// Position and length of a Latin letter in a reading, written either as
+/// the letter itself (half- or full-width) or as its katakana name (C -> シー).
+pub(crate) fn latin_in_reading(reading: &[char], c: char) -> Option<(usize, usize)> {
+    let name: Vec<char> = latin_letter_name(c).map(|n| n.chars().collect()).unwrap_or_default();
+    (0..reading.len()).find_map(|pos| {
+        if ascii_latin(reading[pos]).is_some_and(|r| r.eq_ignore_ascii_case(&c)) {
+            Some((pos, 1))
+        } else if !name.is_empty() && reading[pos..].starts_with(&name) {
+            Some((pos, name.len()))
+        } else {
+            None
+        }
+    })
+}
+
+/// Length of the title-case Latin word (Sakura, Ｔｏｋｙｏ) at the start of
+/// `chars`: one capital followed by at least one lowercase letter.
+pub(crate) fn title_case_word_len(chars: &[char]) -> Option<usize> {
+    let first = ascii_latin(*chars.first()?)?;
+    if !first.is_ascii_uppercase() {
+        return None;
+    }
+    let len = 1 + chars[1..]
+        .iter()
+        .take_while(|&&c| ascii_latin(c).is_some_and(|l| l.is_ascii_lowercase()))
+        .count();
+    (len > 1).then_some(len)
+}
+
+/// Position and length of the first run of `reading`, at or after `from`,
+/// whose romaji spells `word` (サクラ for Sakura).
+pub(crate) fn romaji_in_reading(reading: &[char], word: &str, from: usize) -> Option<(usize, usize)> {
+    (from..reading.len()).find_map(|pos| {
+        (pos + 1..=reading.len()).find_map(|end| {
+            let romaji = kata_to_romaji(&reading[pos..end].iter().collect::<String>()).replace('\'', "");
+            (romaji == word).then_some((pos, end - pos))
+        })
+    })
+}
+
+pub(crate) fn latin_letter_name(c: char) -> Option<&'static str> {
+    let name = match c.to_ascii_uppercase() {
+        'A' => "エー",
+        'B' => "ビー",
+        'C' => "シー",
+        'D' => "ディー",
+        'E' => "イー",
+        'F' => "エフ",
+        'G' => "ジー",
+        'H' => "エイチ",
+        'I' => "アイ",
+        'J' => "ジェー",
+        'K' => "ケー",
+        'L' => "エル",
+        'M' => "エム",
+        'N' => "エヌ",
+        'O' => "オー",
+        'P' => "ピー",
+        'Q' => "キュー",
+        'R' => "アール",
+        'S' => "エス",
+        'T' => "ティー",
+        'U' => "ユー",
+        'V' => "ブイ",
+        'W' => "ダブリュー",
+        'X' => "エックス",
+        'Y' => "ワイ",
+        'Z' => "ゼット",
+        '0' => "ゼロ",
+        '1' => "イチ",
+        '2' => "ニ",
+        '3' => "サン",
+        '4' => "ヨン",
+        '5' => "ゴ",
+        '6' => "ロク",
+        '7' => "ナナ",
+        '8' => "ハチ",
+        '9' => "キュウ",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Builds a letter-by-letter katakana reading for a pure-Latin token.
+/// Hyphens and periods (Wi-Fi, U.S.) are skipped; any other character, or a digit
+/// when digits are not spelled, leaves the token without a reading.
+pub(crate) fn spell_latin(surface: &str, spell_digits: bool) -> Option<String> {
+    if !surface.chars().any(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    let mut reading = String::new();
+    for c in surface.chars() {
+        if c == '-' || c == '.' {
+            continue;
+        }
+        if c.is_ascii_digit() && !spell_digits {
+            return None;
+        }
+        reading.push_str(latin_letter_name(c)?);
+    }
+    Some(reading)
+}
+
+/// The segments read in full (ruby where present, text otherwise) as plain
+/// full-width hiragana.
+pub(crate) fn hiragana_reading(segments: &[RubySegment]) -> String {
+    let reading: String = segments
+        .iter()
+        .map(|seg| if seg.ruby.is_empty() { seg.text.as_str() } else { seg.ruby.as_str() })
+        .collect();
+    normalize_kana(&reading).chars().map(kata_to_hira).collect()
+}
+
+/// Vowel of a kana (か -> 'a'), None for ン and non-kana.
+pub(crate) fn kana_vowel(c: char) -> Option<char> {
+    kana_romaji(hira_to_kata(c))?.chars().last().filter(|v| "aiueo".contains(*v))
+}
+
+/// Whether katakana `long` spells the lengthening of a kana ending in
+/// `vowel`: ー, the vowel itself, or ウ after o and イ after e (トウ, セイ).
+pub(crate) fn lengthens(vowel: char, long: char) -> bool {
+    let plain = match long {
+        'ア' => 'a',
+        'イ' => 'i',
+        'ウ' => 'u',
+        'エ' => 'e',
+        'オ' => 'o',
+        'ー' => return true,
+        _ => return false,
+    };
+    plain == vowel || (vowel, plain) == ('o', 'u') || (vowel, plain) == ('e', 'i')
+}
+
+/// Surface/reading equality that ignores ー (and half-width ｰ), so katakana
+/// words differing only in long-vowel marks (コーヒー/コーヒ) get no ruby.
+pub(crate) fn equal_ignoring_long_vowels(surface: &str, reading: &str) -> bool {
+    let is_long = |c: &char| matches!(c, 'ー' | '\u{FF70}');
+    surface.chars().filter(|c| !is_long(c)).eq(reading.chars().filter(|c| !is_long(c)))
+}
+
+/// Placeholder for a reading: one ● per mora. Anything that is not kana is
+/// kept.
+pub(crate) fn redact_reading(reading: &str) -> String {
+    mora_units(reading)
+        .iter()
+        .map(|mora| if mora.chars().any(|c| is_hiragana(c) || is_katakana(c)) { "●" } else { mora.as_str() })
+        .collect()
+}
+
+pub(crate) fn is_small_kana(c: char) -> bool {
+    matches!(hira_to_kata(c), 'ァ' | 'ィ' | 'ゥ' | 'ェ' | 'ォ' | 'ャ' | 'ュ' | 'ョ' | 'ヮ')
+}
+
+/// Splits a reading into morae; small kana (ャ, ォ, ...) join the preceding
+/// kana, while ッ, ン and ー count as morae of their own.
+pub(crate) fn mora_units(reading: &str) -> Vec<String> {
+    let mut units: Vec<String> = Vec::new();
+    for c in reading.chars() {
+        match units.last_mut() {
+            Some(last) if is_small_kana(c) => last.push(c),
+            _ => units.push(c.to_string()),
+        }
+    }
+    units
+}
@@ -0,0 +1,187 @@
+//! Renderers for analysis results: JSON, JSON Lines, MeCab, Aozora Bunko
+//! markup and compact binary records.
+
+use super::*;
+
+/// 64-bit FNV-1a. FNV is used instead of `DefaultHasher` because its output
+/// is fixed across Rust releases.
+pub(crate) fn fnv1a<'a>(bytes: impl IntoIterator<Item = &'a u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in bytes {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Stable token id: FNV-1a over the byte offset (little-endian u64)
+/// followed by the surface bytes, rendered as 16 hex digits.
+pub(crate) fn token_id(surface: &str, byte_start: usize) -> String {
+    format!("{:016x}", fnv1a((byte_start as u64).to_le_bytes().iter().chain(surface.as_bytes())))
+}
+
+/// Serializes the token list. Should that fail, tokens are serialized one by
+/// one so the ones that succeed are still returned, followed by an
+/// `{"kind":"error"}` marker naming the first failure.
+pub(crate) fn serialize_tokens<T: Serialize>(result_list: &[T]) -> Vec<u8> {
+    let err = match serde_json::to_vec(result_list) {
+        Ok(bytes) => return bytes,
+        Err(e) => e,
+    };
+
+    let mut parts: Vec<Vec<u8>> = result_list.iter().filter_map(|token| serde_json::to_vec(token).ok()).collect();
+    let marker = serde_json::json!({
+        "kind": "error",
+        "error": format!("Serialization failed: {}", err),
+    });
+    parts.push(marker.to_string().into_bytes());
+
+    let mut bytes = b"[".to_vec();
+    bytes.extend(parts.join(&b","[..]));
+    bytes.push(b']');
+    bytes
+}
+
+/// Fraction of kanji characters that sit in a segment with ruby; 1.0 when
+/// the text has no kanji.
+pub(crate) fn reading_coverage(result_list: &[TokenInfo]) -> f64 {
+    let (mut covered, mut total) = (0usize, 0usize);
+    for seg in result_list.iter().flat_map(|token| &token.ruby_segments) {
+        let kanji = seg.text.chars().filter(|&c| is_kanji(c)).count();
+        total += kanji;
+        if !seg.ruby.is_empty() {
+            covered += kanji;
+        }
+    }
+    if total == 0 {
+        1.0
+    } else {
+        covered as f64 / total as f64
+    }
+}
+
+/// Provenance of a result (`include_meta`): dictionary, tokenizer mode and
+/// an FNV-1a hash of every parameter except `text`, serialized with sorted
+/// keys so equal options always hash alike.
+pub(crate) fn result_meta(params: &InputParams) -> serde_json::Value {
+    let mut options = serde_json::to_value(params).unwrap_or_default();
+    if let Some(map) = options.as_object_mut() {
+        map.remove("text");
+    }
+    serde_json::json!({
+        "dict": DICTIONARY_NAME,
+        "mode": "normal",
+        "options_hash": format!("{:016x}", fnv1a(options.to_string().as_bytes())),
+    })
+}
+
+/// Renders tokens as JSON Lines: one object per token, each followed by a
+/// newline. A token that fails to serialize becomes an error marker line.
+pub(crate) fn jsonl_lines(result_list: &[TokenInfo]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for token in result_list {
+        match serde_json::to_vec(token) {
+            Ok(line) => bytes.extend(line),
+            Err(e) => {
+                let marker = serde_json::json!({
+                    "kind": "error",
+                    "error": format!("Serialization failed: {}", e),
+                });
+                bytes.extend(marker.to_string().into_bytes());
+            }
+        }
+        bytes.push(b'\n');
+    }
+    bytes
+}
+
+/// Renders tokens as MeCab text output: one `surface\tf1,f2,...` line per
+/// token, whitespace skipped, followed by `EOS`.
+pub(crate) fn mecab_lines(result_list: &[TokenInfo]) -> Vec<u8> {
+    let mut out = String::new();
+    for token in result_list.iter().filter(|t| t.kind == "word" || t.kind == "symbol") {
+        out.push_str(&token.surface);
+        out.push('\t');
+        out.push_str(&token.features().join(","));
+        out.push('\n');
+    }
+    out.push_str("EOS\n");
+    out.into_bytes()
+}
+
+/// Characters Aozora Bunko takes as a ruby base without a ｜ marker.
+pub(crate) fn is_aozora_ruby_base(c: char) -> bool {
+    is_kanji(c) || matches!(c, '々' | '〆' | 'ヶ' | '〇')
+}
+
+/// Renders the text with Aozora Bunko ruby markup: 漢字《かんじ》. A reader
+/// takes the kanji run before 《 as the base, so ｜ marks where the base
+/// starts whenever that run would be wrong: the base continues a kanji run
+/// (a word split into several segments) or is not all kanji.
+pub(crate) fn aozora_text(result_list: &[TokenInfo]) -> Vec<u8> {
+    let mut out = String::new();
+    // Last character of the text itself, the markup left out.
+    let mut prev: Option<char> = None;
+    for seg in result_list.iter().flat_map(|token| &token.ruby_segments) {
+        let follows_base = prev.is_some_and(is_aozora_ruby_base);
+        prev = seg.text.chars().last().or(prev);
+        if seg.ruby.is_empty() {
+            out.push_str(&seg.text);
+            continue;
+        }
+        if follows_base || !seg.text.chars().all(is_aozora_ruby_base) {
+            out.push('｜');
+        }
+        out.push_str(&seg.text);
+        out.push('《');
+        out.push_str(&seg.ruby);
+        out.push('》');
+    }
+    out.into_bytes()
+}
+
+/// Renders the ruby segments of all tokens as binary records, cheap to
+/// decode in Typst without a JSON parser. Integers are little-endian u32:
+/// the record count, then per segment the byte length of its base text,
+/// the UTF-8 base, the byte length of its ruby and the UTF-8 ruby (empty
+/// for bare text).
+pub(crate) fn compact_records(result_list: &[TokenInfo]) -> Vec<u8> {
+    let segments: Vec<&RubySegment> = result_list.iter().flat_map(|token| &token.ruby_segments).collect();
+    let mut bytes = Vec::new();
+    bytes.extend((segments.len() as u32).to_le_bytes());
+    for seg in segments {
+        for field in [&seg.text, &seg.ruby] {
+            bytes.extend((field.len() as u32).to_le_bytes());
+            bytes.extend(field.as_bytes());
+        }
+    }
+    bytes
+}
+
+/// Byte ranges of Aozora Bunko ruby markup: each non-empty 《reading》 and
+/// the ｜ opening its base, if any. A ｜ or 《 left open at the end of a
+/// line is not markup.
+pub(crate) fn aozora_markup(text: &str) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut bar: Option<usize> = None;
+    let mut open: Option<usize> = None;
+    for (idx, c) in text.char_indices() {
+        match c {
+            '｜' if open.is_none() => bar = Some(idx),
+            '《' if open.is_none() => open = Some(idx),
+            '》' => {
+                if let Some(start) = open.take().filter(|&start| idx > start + '《'.len_utf8()) {
+                    ranges.extend(bar.map(|b| (b, b + '｜'.len_utf8())));
+                    ranges.push((start, idx + c.len_utf8()));
+                }
+                bar = None;
+            }
+            '\n' => {
+                bar = None;
+                open = None;
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
@@ -0,0 +1,246 @@
+//! Options accepted by the analysis entry points.
+
+use super::*;
+
+#[derive(Deserialize, Serialize, Clone)]
+pub(crate) struct InputParams {
+    pub(crate) text: String,
+    #[serde(default)]
+    pub(crate) user_dict_csv: Option<String>,
+    /// Treat small and large kana (ッ/ツ, ャ/ヤ, ...) as equal while aligning okurigana.
+    #[serde(default)]
+    pub(crate) loose_kana_size: bool,
+    /// Byte ranges `[start, end)` of the input whose tokens get no ruby.
+    /// A token is suppressed when its first byte falls inside a range.
+    #[serde(default)]
+    pub(crate) suppress_ranges: Option<Vec<(usize, usize)>>,
+    /// Spell out pure-Latin tokens (acronyms) in katakana letter names, e.g. NHK -> エヌエイチケー.
+    #[serde(default)]
+    pub(crate) latin_to_kana: bool,
+    /// Also spell digits inside Latin tokens when `latin_to_kana` is set.
+    #[serde(default)]
+    pub(crate) latin_digits_to_kana: bool,
+    /// Emit ruby over katakana-only tokens, even when the surface equals the reading.
+    #[serde(default)]
+    pub(crate) annotate_katakana: bool,
+    /// Emit ruby over hiragana-only tokens.
+    #[serde(default)]
+    pub(crate) annotate_hiragana: bool,
+    /// Read administrative suffixes (郡, 市, 町, 村) after a proper noun with their common reading.
+    #[serde(default)]
+    pub(crate) place_name_readings: bool,
+    /// Return `details` as an object keyed by `DETAIL_SCHEMA` instead of an array.
+    #[serde(default)]
+    pub(crate) details_map: bool,
+    /// Attach a stable `id` to every token (see `token_id`).
+    #[serde(default)]
+    pub(crate) include_ids: bool,
+    /// Apply counter readings to digit + 月/日/時/分 (1日 -> ついたち, 3分 -> さんぷん).
+    #[serde(default)]
+    pub(crate) datetime_readings: bool,
+    /// Script of the emitted ruby: "katakana" (default), "hiragana" or "romaji".
+    #[serde(default = "default_output_kana")]
+    pub(crate) output_kana: String,
+    /// Keep the katakana reading in `raw_ruby` when `output_kana` converts it.
+    #[serde(default)]
+    pub(crate) include_raw_reading: bool,
+    /// UniDic only: always read from index 6 (語彙素読み) without reconstruction, like ipadic.
+    #[serde(default)]
+    pub(crate) disable_conjugation_heuristic: bool,
+    /// UniDic only: attach `explain` notes describing how each reading was chosen.
+    #[serde(default)]
+    pub(crate) explain: bool,
+    /// Return tokens last-to-first (segments inside a token keep their order).
+    #[serde(default)]
+    pub(crate) reverse: bool,
+    /// Inclusive codepoint ranges that receive ruby; replaces the built-in kanji blocks.
+    #[serde(default)]
+    pub(crate) ruby_kanji_ranges: Option<Vec<(u32, u32)>>,
+    /// Split a kanji numeral + counter token (三人) into separate numeral and counter tokens.
+    #[serde(default)]
+    pub(crate) split_number_counter: bool,
+    /// Put the whole reading of each kanji run over its first kanji only.
+    #[serde(default)]
+    pub(crate) lead_kanji_only: bool,
+    /// Convert half-width katakana and punctuation (｡｢｣､･) to full-width before
+    /// analysis, composing separate voicing marks (ﾊﾞ -> バ). Offsets still
+    /// refer to the original text.
+    #[serde(default)]
+    pub(crate) normalize_halfwidth: bool,
+    /// Separator inserted between morphemes in `reading_only` output.
+    #[serde(default)]
+    pub(crate) reading_separator: Option<String>,
+    /// Stamp each ruby segment with its parent token's part of speech.
+    #[serde(default)]
+    pub(crate) inherit_pos: bool,
+    /// Also return each token's surface and full reading for token-level ruby.
+    #[serde(default)]
+    pub(crate) token_level_ruby: bool,
+    /// List the other dictionary readings of each token's surface.
+    #[serde(default)]
+    pub(crate) include_alternatives: bool,
+    /// Add a "surface reading" search key (hiragana, voicing marks composed)
+    /// to each kanji-bearing token for reading-based full-text indexing.
+    #[serde(default)]
+    pub(crate) search_keys: bool,
+    /// "group" puts a word's reading over all its kanji; "mono" gives each kanji
+    /// its own mora when the kanji and mora counts match.
+    #[serde(default = "default_ruby_mode")]
+    pub(crate) ruby_mode: String,
+    /// Fail with an error listing every kanji token whose reading could not be
+    /// aligned cleanly instead of silently falling back.
+    #[serde(default)]
+    pub(crate) strict: bool,
+    /// Extra jukujikun/ateji entries as `surface,reading` lines; these words
+    /// always get one ruby over the whole surface.
+    #[serde(default)]
+    pub(crate) jukujikun_csv: Option<String>,
+    /// Attach the dictionary candidates starting at each token (`lattice`).
+    #[serde(default)]
+    pub(crate) debug_lattice: bool,
+    /// Mark okurigana segments with the kanji they belong to (`attaches_to`:
+    /// "prev" or "next").
+    #[serde(default)]
+    pub(crate) tag_okurigana: bool,
+    /// Merge adjacent ruby-less segments of a token (食べさせる -> 食 + べさせる).
+    #[serde(default)]
+    pub(crate) collapse_empty_segments: bool,
+    /// Always keep a leading honorific お/ご (お茶, ご飯) as its own bare
+    /// segment so ruby sits only over the noun.
+    #[serde(default)]
+    pub(crate) honorific_prefix_bare: bool,
+    /// Also return the whole chosen reading of each token as `full_reading`.
+    #[serde(default)]
+    pub(crate) include_full_reading: bool,
+    /// Cap on the number of `alternatives` returned per token.
+    #[serde(default)]
+    pub(crate) max_alternatives: Option<usize>,
+    /// Report the lindera dictionary entry of each token as `word_id`.
+    #[serde(default)]
+    pub(crate) include_word_ids: bool,
+    /// Give symbols such as 〒 and ℃ their spoken reading as ruby.
+    #[serde(default)]
+    pub(crate) symbol_readings: bool,
+    /// Forced segment boundaries per surface, as character offsets into the
+    /// surface (e.g. `{"東京都": [2]}` splits 東京|都).
+    #[serde(default)]
+    pub(crate) split_points: Option<HashMap<String, Vec<usize>>>,
+    /// UniDic only: "surface" makes conjugated readings follow the written
+    /// okurigana (the default); "canonical" keeps their lemma reading. The
+    /// ipadic reading column already follows the written form.
+    #[serde(default)]
+    pub(crate) reading_form: Option<String>,
+    /// Suggest inter-token spacing (`space_before`) from adjacent character classes.
+    #[serde(default)]
+    pub(crate) spacing_hints: bool,
+    /// Re-pick the reading of a few homographs from the preceding particle.
+    #[serde(default)]
+    pub(crate) context_disambiguation: bool,
+    /// Label each kanji segment's reading as "on", "kun", "mixed" or "unknown".
+    #[serde(default)]
+    pub(crate) reading_type: bool,
+    /// Force every ruby to full-width katakana with composed voicing marks
+    /// before `output_kana` conversion, for byte-stable output.
+    #[serde(default)]
+    pub(crate) canonicalize_readings: bool,
+    /// Only analyze this byte window `[start, end)` of `text`, widened to
+    /// whole sentences. Offsets stay relative to the full text.
+    #[serde(default)]
+    pub(crate) range: Option<(usize, usize)>,
+    /// Apply euphonic counter readings to kanji numerals (三匹 -> さん+びき,
+    /// 六本 -> ろっ+ぽん, 八百 -> はっ+ぴゃく).
+    #[serde(default)]
+    pub(crate) counter_readings: bool,
+    /// Output format of `analyze`: "json" (default), "jsonl" (one token object
+    /// per line), "mecab" (`surface\tfeatures` lines terminated by `EOS`),
+    /// "aozora" (the text with Aozora Bunko ruby markup, ｜漢字《かんじ》) or
+    /// "compact" (length-prefixed base/ruby records, see `compact_records`).
+    #[serde(default)]
+    pub(crate) output: Option<String>,
+    /// Turn author-written readings such as 漢字（かんじ） into ruby over the
+    /// kanji and drop the parenthetical from the output.
+    #[serde(default)]
+    pub(crate) strip_inline_reading: bool,
+    /// Only these kanji receive ruby; other segments are left bare.
+    #[serde(default)]
+    pub(crate) target_kanji: Option<Vec<char>>,
+    /// Emit a token as one group-ruby segment when its confidence is below
+    /// this value, instead of a per-character split.
+    #[serde(default)]
+    pub(crate) group_ruby_below_confidence: Option<f32>,
+    /// Add `word` and a hiragana `reading_hira` to kanji-bearing tokens.
+    #[serde(default)]
+    pub(crate) glossary_fields: bool,
+    /// Re-merge consecutive tokens that spell a user dictionary surface and
+    /// give them the user dictionary reading as group ruby.
+    #[serde(default)]
+    pub(crate) merge_user_dict_splits: bool,
+    /// Add a `warnings` array to tokens whose reading contains kanji.
+    #[serde(default)]
+    pub(crate) reading_warnings: bool,
+    /// "forward" aligns kana left to right; "bidirectional" first anchors the
+    /// kana at both ends of a word and aligns only the middle.
+    #[serde(default = "default_align")]
+    pub(crate) align: String,
+    /// Wrap the result as `{meta, tokens}`, with `meta` recording the
+    /// dictionary, tokenizer mode and a hash of the options.
+    #[serde(default)]
+    pub(crate) include_meta: bool,
+    /// Return only the fraction of kanji in the input that received ruby.
+    #[serde(default)]
+    pub(crate) coverage: bool,
+    /// Split tokens at ・ (katakana name separator) into separate tokens.
+    #[serde(default)]
+    pub(crate) split_on_nakaguro: bool,
+    /// UniDic only: pitch accent in a `pitch` field, "none" (default),
+    /// "numeric" (accent type) or "mark" (reading with ＼ after the accented
+    /// mora). Needs the UniDic `aType` column.
+    #[serde(default = "default_pitch_notation")]
+    pub(crate) pitch_notation: String,
+    /// Give group-ruby segments a `distribution` of their reading's morae
+    /// over the base characters.
+    #[serde(default)]
+    pub(crate) distribute_group_ruby: bool,
+    /// Replace every reading with ● per mora, keeping the segment structure.
+    #[serde(default)]
+    pub(crate) redact_readings: bool,
+    /// Words the reader already knows: tokens whose surface or base form is
+    /// listed get no ruby, so conjugations of a known verb are covered too.
+    #[serde(default)]
+    pub(crate) known_words: Option<Vec<String>>,
+    /// Add each token's length in grapheme clusters (`grapheme_count`).
+    #[serde(default)]
+    pub(crate) grapheme_count: bool,
+    /// Ruby script per part of speech, overriding `output_kana`: keys are a
+    /// POS ("名詞") or POS and first subcategory ("名詞-固有名詞").
+    #[serde(default)]
+    pub(crate) kana_by_pos: Option<HashMap<String, String>>,
+    /// Marker inserted into `full_reading` where ruby starts or ends, i.e. at
+    /// kanji/okurigana boundaries (食べる -> タ‧ベル). Implies the field.
+    #[serde(default)]
+    pub(crate) mark_okurigana: Option<String>,
+    /// Align informal lengthening (ありがとー, 凄ーい, そお) with standard
+    /// readings: the ー or repeated vowel stays bare instead of taking ruby.
+    #[serde(default)]
+    pub(crate) tolerate_lengthening: bool,
+}
+
+fn default_output_kana() -> String {
+    "katakana".to_string()
+}
+
+fn default_ruby_mode() -> String {
+    "group".to_string()
+}
+
+fn default_align() -> String {
+    "forward".to_string()
+}
+
+fn default_pitch_notation() -> String {
+    "none".to_string()
+}
+
+pub(crate) fn parse_params(input_bytes: &[u8]) -> Result<InputParams, String> {
+    serde_json::from_slice(input_bytes).map_err(|e| format!("Error: Invalid JSON: {}", e))
+}
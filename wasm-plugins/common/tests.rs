@@ -0,0 +1,1159 @@
+use super::*;
+
+fn params(options: serde_json::Value) -> InputParams {
+    let mut value = serde_json::json!({ "text": "" });
+    if let (Some(map), Some(options)) = (value.as_object_mut(), options.as_object()) {
+        map.extend(options.clone());
+    }
+    serde_json::from_value(value).expect("valid options")
+}
+
+fn pairs(segments: &[RubySegment]) -> Vec<(&str, &str)> {
+    segments.iter().map(|seg| (seg.text.as_str(), seg.ruby.as_str())).collect()
+}
+
+fn align(surface: &str, reading: &str, options: serde_json::Value) -> Vec<RubySegment> {
+    build_ruby_segments(surface, reading, &params(options))
+}
+
+fn tokens_for(text: &str, options: serde_json::Value) -> Vec<TokenInfo> {
+    let mut params = params(options);
+    params.text = text.to_string();
+    analyze_tokens(&params).expect("analysis succeeds")
+}
+
+fn token<'a>(tokens: &'a [TokenInfo], surface: &str) -> &'a TokenInfo {
+    tokens.iter().find(|token| token.surface == surface).unwrap_or_else(|| panic!("no token {:?}", surface))
+}
+
+#[test]
+fn okonomiyaki_alignment() {
+    let segments = align("お好み焼き", "オコノミヤキ", serde_json::json!({}));
+    assert_eq!(pairs(&segments), [("お", ""), ("好", "コノ"), ("み", ""), ("焼", "ヤ"), ("き", "")]);
+}
+
+#[test]
+fn okonomiyaki_analysis() {
+    let tokens = tokens_for("お好み焼きを食べた", serde_json::json!({ "user_dict_csv": "お好み焼き,名詞,オコノミヤキ" }));
+    let okonomiyaki = token(&tokens, "お好み焼き");
+    assert_eq!(pairs(&okonomiyaki.ruby_segments), [("お", ""), ("好", "コノ"), ("み", ""), ("焼", "ヤ"), ("き", "")]);
+}
+
+fn attachments(segments: &[RubySegment]) -> Vec<(&str, Option<&str>)> {
+    segments.iter().map(|seg| (seg.text.as_str(), seg.attaches_to)).collect()
+}
+
+#[test]
+fn okurigana_attaches_to_preceding_kanji() {
+    let segments = token_ruby_segments("書き込む", "カキコム", 0, &params(serde_json::json!({ "tag_okurigana": true })));
+    assert_eq!(attachments(&segments), [("書", None), ("き", Some("prev")), ("込", None), ("む", Some("prev"))]);
+}
+
+#[test]
+fn prefix_attaches_to_following_kanji() {
+    let segments = token_ruby_segments("お菓子", "オカシ", 0, &params(serde_json::json!({ "tag_okurigana": true })));
+    assert_eq!(attachments(&segments), [("お", Some("next")), ("菓子", None)]);
+}
+
+#[test]
+fn okurigana_untagged_by_default() {
+    let segments = token_ruby_segments("書き込む", "カキコム", 0, &params(serde_json::json!({})));
+    assert!(segments.iter().all(|seg| seg.attaches_to.is_none()));
+}
+
+#[test]
+fn cached_segments_match_and_reuse_the_stored_entry() {
+    let params = params(serde_json::json!({ "ruby_mode": "mono" }));
+    let (surface, reading) = ("東京都特許許可局", "トウキョウトトッキョキョカキョク");
+
+    let mut cache = HashMap::new();
+    assert_eq!(
+        pairs(&cached_ruby_segments(&mut cache, surface, reading, 0, &params)),
+        pairs(&token_ruby_segments(surface, reading, 0, &params))
+    );
+    assert_eq!(cache.len(), 1);
+
+    // A hit returns the stored entry without aligning again.
+    let key = (surface.to_string(), reading.to_string());
+    let marker = vec![RubySegment { text: surface.to_string(), ruby: "キャッシュ".to_string(), ..Default::default() }];
+    cache.insert(key, marker);
+    assert_eq!(pairs(&cached_ruby_segments(&mut cache, surface, reading, 0, &params)), [(surface, "キャッシュ")]);
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn confidence_follows_segments() {
+    let clean = align("漢字", "カンジ", serde_json::json!({}));
+    assert_eq!(alignment_confidence(&clean, None), 1.0);
+    assert_eq!(alignment_confidence(&clean, Some(MEDIUM_CONFIDENCE)), MEDIUM_CONFIDENCE);
+
+    let swallowed = [RubySegment { text: "書き".to_string(), ruby: "カキ".to_string(), ..Default::default() }];
+    assert_eq!(alignment_confidence(&swallowed, None), LOW_CONFIDENCE);
+    let bare = [RubySegment { text: "書".to_string(), ..Default::default() }];
+    assert_eq!(alignment_confidence(&bare, None), LOW_CONFIDENCE);
+}
+
+#[test]
+fn low_confidence_token_gets_group_ruby() {
+    let options = serde_json::json!({ "user_dict_csv": "書き物,名詞,ヨミモノ", "group_ruby_below_confidence": 0.5 });
+    let tokens = tokens_for("書き物", options);
+    let word = token(&tokens, "書き物");
+    assert_eq!(pairs(&word.ruby_segments), [("書き物", "ヨミモノ")]);
+    assert_eq!(word.confidence, Some(LOW_CONFIDENCE));
+}
+
+#[test]
+fn suppress_ranges_use_original_offsets_after_normalization() {
+    let options = serde_json::json!({
+        "user_dict_csv": "東京,名詞,トウキョウ\n学校,名詞,ガッコウ",
+        "normalize_halfwidth": true,
+        "suppress_ranges": [[12, 18]],
+    });
+    let tokens = tokens_for("ﾊﾞｽで東京の学校", options);
+    let tokyo = token(&tokens, "東京");
+    assert_eq!((tokyo.byte_start, tokyo.byte_end), (12, 18));
+    assert_eq!(pairs(&tokyo.ruby_segments), [("東京", "")]);
+    assert_eq!(pairs(&token(&tokens, "学校").ruby_segments), [("学校", "ガッコウ")]);
+}
+
+#[test]
+fn leading_ideographic_spaces_are_split() {
+    let tokens = tokens_for("\u{3000}\u{3000}東京", serde_json::json!({ "user_dict_csv": "\u{3000}\u{3000},記号,*\n東京,名詞,トウキョウ" }));
+    let kinds: Vec<(&str, &str, usize)> = tokens.iter().map(|token| (token.surface.as_str(), token.kind, token.byte_start)).collect();
+    assert_eq!(kinds, [("\u{3000}", "ideographic_space", 0), ("\u{3000}", "ideographic_space", 3), ("東京", "word", 6)]);
+}
+
+fn counter_options(suppress_ranges: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "user_dict_csv": "一,名詞,イチ\n本,名詞,ホン",
+        "counter_readings": true,
+        "suppress_ranges": suppress_ranges,
+    })
+}
+
+#[test]
+fn counter_reading_rewrites_numeral() {
+    let tokens = tokens_for("一本", counter_options(serde_json::json!([])));
+    assert_eq!(pairs(&token(&tokens, "一").ruby_segments), [("一", "イッ")]);
+    assert_eq!(pairs(&token(&tokens, "本").ruby_segments), [("本", "ポン")]);
+}
+
+#[test]
+fn counter_reading_keeps_suppressed_numeral_bare() {
+    let tokens = tokens_for("一本", counter_options(serde_json::json!([[0, 3]])));
+    assert_eq!(pairs(&token(&tokens, "一").ruby_segments), [("一", "")]);
+    assert_eq!(pairs(&token(&tokens, "本").ruby_segments), [("本", "ポン")]);
+}
+
+#[test]
+fn counter_reading_covers_irregular_compounds() {
+    let expected = [("三匹", ("サン", "ビキ")), ("六本", ("ロッ", "ポン")), ("八百", ("ハッ", "ピャク"))];
+    let split = serde_json::json!({
+        "user_dict_csv": "三,名詞,サン\n六,名詞,ロク\n八,名詞,ハチ\n匹,名詞,ヒキ\n本,名詞,ホン\n百,名詞,ヒャク",
+        "counter_readings": true,
+    });
+    for (text, (numeral, counter)) in expected {
+        let (first, second) = text.split_at(3);
+        let tokens = tokens_for(text, split.clone());
+        assert_eq!(pairs(&token(&tokens, first).ruby_segments), [(first, numeral)]);
+        assert_eq!(pairs(&token(&tokens, second).ruby_segments), [(second, counter)]);
+    }
+
+    // A compound dictionary entry with a plain reading gets the same split.
+    let compound = serde_json::json!({ "user_dict_csv": "三匹,名詞,サンヒキ\n六本,名詞,ロクホン\n八百,名詞,ハチヒャク", "counter_readings": true });
+    for (text, (numeral, counter)) in expected {
+        let (first, second) = text.split_at(3);
+        let tokens = tokens_for(text, compound.clone());
+        assert_eq!(pairs(&token(&tokens, text).ruby_segments), [(first, numeral), (second, counter)]);
+    }
+}
+
+fn date_options(suppress_ranges: serde_json::Value) -> serde_json::Value {
+    serde_json::json!({
+        "user_dict_csv": "日,名詞,ニチ\n20,名詞,ニジュウ",
+        "datetime_readings": true,
+        "suppress_ranges": suppress_ranges,
+    })
+}
+
+#[test]
+fn datetime_reading_rewrites_day_number() {
+    let tokens = tokens_for("1日", date_options(serde_json::json!([])));
+    assert_eq!(pairs(&token(&tokens, "1").ruby_segments), [("1", "ツイ")]);
+    assert_eq!(pairs(&token(&tokens, "日").ruby_segments), [("日", "タチ")]);
+
+    let tokens = tokens_for("20日", date_options(serde_json::json!([])));
+    assert_eq!(pairs(&token(&tokens, "20").ruby_segments), [("20", "ハツ")]);
+    assert_eq!(pairs(&token(&tokens, "日").ruby_segments), [("日", "カ")]);
+}
+
+#[test]
+fn datetime_reading_makes_minutes_euphonic() {
+    assert_eq!(datetime_reading(3, "分"), Some((None, "プン")));
+    assert_eq!(datetime_reading(8, "分"), Some((Some("ハッ"), "プン")));
+    assert_eq!(datetime_reading(20, "分"), Some((None, "プン")));
+
+    let user_dict = "分,名詞,フン\n3,名詞,サン\n8,名詞,ハチ\n20,名詞,ニジュウ";
+    let on = serde_json::json!({ "user_dict_csv": user_dict, "datetime_readings": true });
+    let off = serde_json::json!({ "user_dict_csv": user_dict });
+    for number in ["3", "8", "20"] {
+        let text = format!("{}分", number);
+        let tokens = tokens_for(&text, on.clone());
+        assert_eq!(pairs(&token(&tokens, "分").ruby_segments), [("分", "プン")]);
+        let number_ruby = pairs(&token(&tokens, number).ruby_segments);
+        if number == "8" {
+            assert_eq!(number_ruby, [("8", "ハッ")]);
+        } else {
+            // No sound change: the number keeps its ordinary ruby.
+            assert_eq!(number_ruby, pairs(&token(&tokens_for(&text, off.clone()), number).ruby_segments));
+        }
+    }
+}
+
+#[test]
+fn datetime_reading_keeps_suppressed_number_bare() {
+    let tokens = tokens_for("1日", date_options(serde_json::json!([[0, 1]])));
+    assert_eq!(pairs(&token(&tokens, "1").ruby_segments), [("1", "")]);
+    assert_eq!(pairs(&token(&tokens, "日").ruby_segments), [("日", "タチ")]);
+
+    let tokens = tokens_for("20日", date_options(serde_json::json!([[0, 2]])));
+    assert_eq!(pairs(&token(&tokens, "20").ruby_segments), [("20", "")]);
+    assert_eq!(pairs(&token(&tokens, "日").ruby_segments), [("日", "カ")]);
+}
+
+fn span_pairs(input: serde_json::Value) -> Vec<Vec<(String, String)>> {
+    let output = build_ruby_for_spans(input.to_string().as_bytes());
+    let spans: serde_json::Value = serde_json::from_slice(&output).unwrap_or_else(|_| panic!("{}", String::from_utf8_lossy(&output)));
+    spans
+        .as_array()
+        .expect("span array")
+        .iter()
+        .map(|span| {
+            span["ruby_segments"]
+                .as_array()
+                .expect("segment array")
+                .iter()
+                .map(|seg| (seg["text"].as_str().unwrap().to_string(), seg["ruby"].as_str().unwrap().to_string()))
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn span_reading_covers_several_kanji_runs() {
+    let spans = span_pairs(serde_json::json!({
+        "spans": [{ "text": "読み書き", "reading": "よみかき" }, { "text": "東京", "reading": "とうきょう" }],
+        "output_kana": "hiragana",
+        "suppress_ranges": [[12, 18]],
+    }));
+    let expected = |pairs: &[(&str, &str)]| pairs.iter().map(|&(text, ruby)| (text.to_string(), ruby.to_string())).collect::<Vec<_>>();
+    assert_eq!(spans, [expected(&[("読", "よ"), ("み", ""), ("書", "か"), ("き", "")]), expected(&[("東京", "")])]);
+}
+
+#[test]
+fn span_options_are_validated_without_a_tokenizer() {
+    let output = build_ruby_for_spans(serde_json::json!({ "spans": [], "ruby_mode": "word" }).to_string().as_bytes());
+    assert_eq!(String::from_utf8_lossy(&output), "Error: ruby_mode must be one of: group, mono");
+}
+
+#[test]
+fn sound_changed_verbs_align() {
+    for (surface, reading, expected) in [
+        ("買って", "カッテ", [("買", "カ"), ("っ", ""), ("て", "")]),
+        ("泳いで", "オヨイデ", [("泳", "オヨ"), ("い", ""), ("で", "")]),
+        ("読んで", "ヨンデ", [("読", "ヨ"), ("ん", ""), ("で", "")]),
+    ] {
+        assert_eq!(pairs(&align(surface, reading, serde_json::json!({}))), expected, "{}", surface);
+    }
+}
+
+#[test]
+fn sound_changed_verbs_align_with_dictionary_form_reading() {
+    for (surface, reading, expected) in [
+        ("買って", "カウ", [("買", "カ"), ("って", "")]),
+        ("泳いで", "オヨグ", [("泳", "オヨ"), ("いで", "")]),
+        ("読んで", "ヨム", [("読", "ヨ"), ("んで", "")]),
+    ] {
+        assert_eq!(pairs(&align(surface, reading, serde_json::json!({}))), expected, "{}", surface);
+    }
+}
+
+#[test]
+fn reading_only_particle_follows_kana_by_pos() {
+    let input = serde_json::json!({
+        "text": "私は",
+        "user_dict_csv": "私,名詞,ワタシ\nは,助詞,ハ",
+        "output_kana": "hiragana",
+        "kana_by_pos": { "助詞": "katakana" },
+    });
+    assert_eq!(String::from_utf8_lossy(&reading_only(input.to_string().as_bytes())), "わたしワ");
+}
+
+#[test]
+fn token_level_ruby_keeps_segments() {
+    let tokens = tokens_for("書き込む", serde_json::json!({ "user_dict_csv": "書き込む,動詞,カキコム", "token_level_ruby": true }));
+    let word = token(&tokens, "書き込む");
+    assert_eq!(pairs(&word.ruby_segments), [("書", "カ"), ("き", ""), ("込", "コ"), ("む", "")]);
+    let joined: String = word.ruby_segments.iter().map(|seg| if seg.ruby.is_empty() { seg.text.as_str() } else { seg.ruby.as_str() }).collect();
+    assert_eq!((word.base.as_deref(), word.rt.as_deref()), (Some("書き込む"), Some(joined.as_str())));
+}
+
+#[test]
+fn katakana_surface_gets_ruby_only_with_annotate_katakana() {
+    assert_eq!(pairs(&align("バス", "バス", serde_json::json!({}))), [("バス", "")]);
+    assert_eq!(pairs(&align("バス", "バス", serde_json::json!({ "annotate_katakana": true }))), [("バス", "バス")]);
+    assert_eq!(pairs(&align("漢字", "カンジ", serde_json::json!({ "annotate_katakana": true }))), [("漢字", "カンジ")]);
+}
+
+#[test]
+fn hiragana_surface_gets_ruby_only_with_annotate_hiragana() {
+    assert!(align("ねこ", "ネコ", serde_json::json!({})).iter().all(|seg| seg.ruby.is_empty()));
+    assert_eq!(pairs(&align("ねこ", "ネコ", serde_json::json!({ "annotate_hiragana": true }))), [("ねこ", "ネコ")]);
+    assert_eq!(pairs(&align("バス", "バス", serde_json::json!({ "annotate_hiragana": true }))), [("バス", "")]);
+
+    let user_dict = "ねこ,名詞,ネコ\n猫,名詞,ネコ";
+    let plain = tokens_for("ねこ猫", serde_json::json!({ "user_dict_csv": user_dict }));
+    assert!(token(&plain, "ねこ").ruby_segments.iter().all(|seg| seg.ruby.is_empty()));
+    let annotated = tokens_for("ねこ猫", serde_json::json!({ "user_dict_csv": user_dict, "annotate_hiragana": true }));
+    assert_eq!(pairs(&token(&annotated, "ねこ").ruby_segments), [("ねこ", "ネコ")]);
+    assert_eq!(pairs(&token(&annotated, "猫").ruby_segments), pairs(&token(&plain, "猫").ruby_segments));
+}
+
+/// A detailed user dictionary row for this plugin's feature layout.
+fn detailed_row(surface: &str, pos: &str, sub_pos: &str, reading: &str) -> String {
+    let mut features = vec!["*"; DETAIL_SCHEMA.len()];
+    features[0] = pos;
+    features[1] = sub_pos;
+    features[BASE_FORM_INDEX] = surface;
+    features[READING_INDEX] = reading;
+    format!("{},0,0,0,{}", surface, features.join(","))
+}
+
+#[test]
+fn place_suffix_after_proper_noun() {
+    let user_dict = [detailed_row("富良野", "名詞", "固有名詞", "フラノ"), detailed_row("町", "名詞", "一般", "マチ")].join("\n");
+    let tokens = tokens_for("富良野町", serde_json::json!({ "user_dict_csv": user_dict, "place_name_readings": true }));
+    let town = token(&tokens, "町");
+    assert_eq!(pairs(&town.ruby_segments), [("町", "チョウ")]);
+    assert_eq!(town.confidence, Some(MEDIUM_CONFIDENCE));
+
+    let tokens = tokens_for("富良野町", serde_json::json!({ "user_dict_csv": user_dict }));
+    assert_eq!(pairs(&token(&tokens, "町").ruby_segments), [("町", "マチ")]);
+}
+
+fn analyze_json(text: &str, options: serde_json::Value) -> serde_json::Value {
+    let mut input = serde_json::json!({ "text": text });
+    if let (Some(map), Some(options)) = (input.as_object_mut(), options.as_object()) {
+        map.extend(options.clone());
+    }
+    let output = analyze(input.to_string().as_bytes());
+    serde_json::from_slice(&output).unwrap_or_else(|_| panic!("{}", String::from_utf8_lossy(&output)))
+}
+
+#[test]
+fn details_map_is_keyed_by_schema() {
+    let user_dict = detailed_row("東京", "名詞", "固有名詞", "トウキョウ");
+    let tokens = analyze_json("東京", serde_json::json!({ "user_dict_csv": user_dict, "details_map": true }));
+    let details = tokens[0]["details"].as_object().expect("details object");
+    let mut keys: Vec<&str> = details.keys().map(String::as_str).collect();
+    let mut schema = DETAIL_SCHEMA.to_vec();
+    keys.sort_unstable();
+    schema.sort_unstable();
+    assert_eq!(keys, schema);
+    assert_eq!(details[DETAIL_SCHEMA[0]], "名詞");
+}
+
+#[test]
+fn iteration_mark_at_token_start_uses_previous_kanji() {
+    let tokens = tokens_for("人々", serde_json::json!({ "user_dict_csv": "人,名詞,ヒト" }));
+    assert_eq!(pairs(&token(&tokens, "人").ruby_segments), [("人", "ヒト")]);
+    assert_eq!(pairs(&token(&tokens, "々").ruby_segments), [("々", "ビト")]);
+}
+
+#[test]
+fn token_ids_are_stable_and_local() {
+    let options = serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ\n京都,名詞,キョウト\n学校,名詞,ガッコウ", "include_ids": true });
+    let ids = |text: &str| -> Vec<String> { tokens_for(text, options.clone()).into_iter().map(|token| token.id.expect("id")).collect() };
+    assert_eq!(ids("東京の学校"), ids("東京の学校"));
+
+    let (before, after) = (ids("東京の学校"), ids("京都の学校"));
+    assert_ne!(before[0], after[0]);
+    assert_eq!(before[1..], after[1..]);
+}
+
+#[test]
+fn raw_reading_stays_katakana() {
+    let tokens = tokens_for("東京", serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ", "output_kana": "hiragana", "include_raw_reading": true }));
+    let segment = &token(&tokens, "東京").ruby_segments[0];
+    assert_eq!((segment.ruby.as_str(), segment.raw_ruby.as_deref()), ("とうきょう", Some("トウキョウ")));
+}
+
+#[test]
+fn reverse_flips_token_order_only() {
+    let user_dict = "東京,名詞,トウキョウ\n書き込む,動詞,カキコム";
+    let forward = tokens_for("東京に書き込む", serde_json::json!({ "user_dict_csv": user_dict }));
+    let mut reversed = tokens_for("東京に書き込む", serde_json::json!({ "user_dict_csv": user_dict, "reverse": true }));
+    assert_eq!(reversed[0].surface, "書き込む");
+    reversed.reverse();
+    for (forward, reversed) in forward.iter().zip(&reversed) {
+        assert_eq!((&forward.surface, forward.byte_start, forward.byte_end), (&reversed.surface, reversed.byte_start, reversed.byte_end));
+        assert_eq!(pairs(&forward.ruby_segments), pairs(&reversed.ruby_segments));
+    }
+    assert_eq!(forward.len(), reversed.len());
+}
+
+#[test]
+fn ruby_kanji_ranges_limit_ruby_to_extension_b() {
+    let options = serde_json::json!({ "user_dict_csv": "𠮷,名詞,ヨシ\n田,名詞,タ", "ruby_kanji_ranges": [[0x20000, 0x2A6DF]] });
+    let tokens = tokens_for("𠮷田", options);
+    assert_eq!(pairs(&token(&tokens, "𠮷").ruby_segments), [("𠮷", "ヨシ")]);
+    assert_eq!(pairs(&token(&tokens, "田").ruby_segments), [("田", "")]);
+}
+
+#[test]
+fn split_number_counter_splits_joined_tokens() {
+    let options = serde_json::json!({ "user_dict_csv": "三人,名詞,サンニン\n五回,名詞,ゴカイ", "split_number_counter": true });
+    let tokens = tokens_for("三人五回", options);
+    let offsets: Vec<(&str, usize, usize)> = tokens.iter().map(|token| (token.surface.as_str(), token.byte_start, token.byte_end)).collect();
+    assert_eq!(offsets, [("三", 0, 3), ("人", 3, 6), ("五", 6, 9), ("回", 9, 12)]);
+    let readings: Vec<(&str, &str)> = tokens.iter().flat_map(|token| pairs(&token.ruby_segments)).collect();
+    assert_eq!(readings, [("三", "サン"), ("人", "ニン"), ("五", "ゴ"), ("回", "カイ")]);
+}
+
+#[test]
+fn lead_kanji_only_keeps_ruby_on_first_kanji() {
+    let segments = token_ruby_segments("学校", "ガッコウ", 0, &params(serde_json::json!({ "lead_kanji_only": true })));
+    assert_eq!(pairs(&segments), [("学", "ガッコウ"), ("校", "")]);
+}
+
+#[test]
+fn separate_voicing_marks_are_composed() {
+    let options = serde_json::json!({ "user_dict_csv": "バス,名詞,バス", "normalize_halfwidth": true });
+    for text in ["\u{FF8A}\u{FF9E}\u{FF7D}", "ハ\u{3099}ス"] {
+        let tokens = tokens_for(text, options.clone());
+        let bus = token(&tokens, "バス");
+        assert_eq!((bus.byte_start, bus.byte_end), (0, text.len()));
+    }
+}
+
+#[test]
+fn reading_separator_sits_between_morphemes() {
+    let input = serde_json::json!({ "text": "私は学校", "user_dict_csv": "私,名詞,ワタシ\nは,助詞,ハ\n学校,名詞,ガッコウ", "reading_separator": "|" });
+    assert_eq!(String::from_utf8_lossy(&reading_only(input.to_string().as_bytes())), "ワタシ|ワ|ガッコウ");
+}
+
+#[test]
+fn halfwidth_prolonged_mark_is_normalized() {
+    assert_eq!(normalize_kana("\u{FF7A}\u{FF70}\u{FF8B}\u{FF70}"), "コーヒー");
+    let text = "\u{FF7A}\u{FF70}\u{FF8B}\u{FF70}";
+    let tokens = tokens_for(text, serde_json::json!({ "user_dict_csv": "コーヒー,名詞,コーヒー", "normalize_halfwidth": true }));
+    let coffee = token(&tokens, "コーヒー");
+    assert_eq!((coffee.byte_start, coffee.byte_end), (0, text.len()));
+}
+
+#[test]
+fn inherit_pos_stamps_every_segment() {
+    let tokens = tokens_for("書き込む", serde_json::json!({ "user_dict_csv": "書き込む,動詞,カキコム", "inherit_pos": true }));
+    let verb = token(&tokens, "書き込む");
+    assert_eq!(verb.ruby_segments.len(), 4);
+    assert!(verb.ruby_segments.iter().all(|seg| seg.pos.as_deref() == Some("動詞")));
+}
+
+#[test]
+fn user_dict_costs_are_validated_and_clamped() {
+    let row = |cost: &str| format!("東京都,0,0,{},名詞,固有名詞,地域,一般,*,*,東京都,トウキョウト,トーキョート", cost);
+    assert_eq!(normalize_user_dict_csv(&row("-100000")).unwrap(), row("-32768"));
+    assert_eq!(
+        normalize_user_dict_csv(&row("low")).unwrap_err(),
+        "Error: User dictionary row 1: cost must be an integer, got \"low\""
+    );
+
+    let tokens = tokens_for("東京都", serde_json::json!({ "user_dict_csv": detailed_row("東京都", "名詞", "固有名詞", "トウキョウト") }));
+    assert_eq!(tokens.iter().map(|token| token.surface.as_str()).collect::<Vec<_>>(), ["東京都"]);
+}
+
+#[test]
+fn single_kanji_takes_the_chosen_reading() {
+    for (kanji, reading) in [("生", "ナマ"), ("行", "ギョウ"), ("下", "シタ")] {
+        assert_eq!(pairs(&align(kanji, reading, serde_json::json!({}))), [(kanji, reading)]);
+        assert_eq!(pairs(&align(kanji, "*", serde_json::json!({}))), [(kanji, "")]);
+    }
+}
+
+#[test]
+fn single_kanji_lists_its_other_readings() {
+    let known = [("生", ["ナマ", "セイ", "ショウ", "キ"]), ("行", ["ギョウ", "コウ", "イ", "ユ"]), ("下", ["シタ", "カ", "ゲ", "シモ"])];
+    for (kanji, readings) in known {
+        let tokens = tokens_for(kanji, serde_json::json!({ "include_alternatives": true }));
+        let chosen = tokens[0].ruby_segments[0].ruby.as_str();
+        assert!(readings.contains(&chosen), "{} read {:?}", kanji, chosen);
+        let alternatives = tokens[0].alternatives.as_deref().expect("alternatives listed");
+        assert!(!alternatives.iter().any(|reading| reading == chosen), "{}: {:?}", kanji, alternatives);
+        assert!(readings.iter().any(|&reading| reading != chosen && alternatives.iter().any(|r| r == reading)), "{}: {:?}", kanji, alternatives);
+
+        let tokens = tokens_for(kanji, serde_json::json!({}));
+        assert!(tokens[0].alternatives.is_none());
+    }
+}
+
+/// Serializes as its number, or fails for zero.
+struct Flaky(u32);
+
+impl Serialize for Flaky {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            0 => Err(serde::ser::Error::custom("zero")),
+            n => serializer.serialize_u32(n),
+        }
+    }
+}
+
+#[test]
+fn serialization_failure_keeps_partial_results() {
+    let output: serde_json::Value = serde_json::from_slice(&serialize_tokens(&[Flaky(1), Flaky(0), Flaky(2)])).expect("valid JSON");
+    assert_eq!(output, serde_json::json!([1, 2, { "kind": "error", "error": "Serialization failed: zero" }]));
+}
+
+#[test]
+fn search_keys_pair_surface_and_hiragana() {
+    let tokens = tokens_for("東京の学校", serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ\n学校,名詞,ガッコウ", "search_keys": true }));
+    let keys: Vec<(&str, Option<&str>)> = tokens.iter().map(|token| (token.surface.as_str(), token.search_key.as_deref())).collect();
+    assert_eq!(keys, [("東京", Some("東京 とうきょう")), ("の", None), ("学校", Some("学校 がっこう"))]);
+}
+
+fn token_segments(surface: &str, reading: &str, options: serde_json::Value) -> Vec<RubySegment> {
+    token_ruby_segments(surface, reading, 0, &params(options))
+}
+
+#[test]
+fn mono_ruby_gives_one_mora_per_kanji() {
+    assert_eq!(pairs(&token_segments("和紙", "ワシ", serde_json::json!({ "ruby_mode": "mono" }))), [("和", "ワ"), ("紙", "シ")]);
+    assert_eq!(pairs(&token_segments("和紙", "ワシ", serde_json::json!({}))), [("和紙", "ワシ")]);
+}
+
+#[test]
+fn suru_verbs_leave_the_stem_bare() {
+    assert!(align("メールする", "メールスル", serde_json::json!({})).iter().all(|seg| seg.ruby.is_empty()));
+    let love = align("愛する", "アイスル", serde_json::json!({}));
+    assert_eq!(pairs(&love[..1]), [("愛", "アイ")]);
+    assert!(love[1..].iter().all(|seg| seg.ruby.is_empty()));
+}
+
+#[test]
+fn strict_mode_reports_fallbacks() {
+    let mut params = params(serde_json::json!({ "user_dict_csv": "書き物,名詞,ヨミモノ", "strict": true }));
+    params.text = "書き物".to_string();
+    let err = analyze_tokens(&params).err().expect("strict mode fails");
+    assert!(err.starts_with("Error: Strict mode: alignment fell back for 1 token(s)"), "{}", err);
+    assert!(err.contains("\"surface\":\"書き物\""), "{}", err);
+
+    params.strict = false;
+    assert!(analyze_tokens(&params).is_ok());
+}
+
+#[test]
+fn latin_runs_stay_bare() {
+    assert_eq!(pairs(&align("CD版", "シーディーバン", serde_json::json!({}))), [("C", ""), ("D", ""), ("版", "バン")]);
+    assert_eq!(pairs(&align("T細胞", "ティーサイボウ", serde_json::json!({}))), [("T", ""), ("細胞", "サイボウ")]);
+}
+
+#[test]
+fn ateji_get_whole_word_ruby() {
+    for options in [serde_json::json!({}), serde_json::json!({ "ruby_mode": "mono" })] {
+        assert_eq!(pairs(&token_segments("珈琲", "コーヒー", options.clone())), [("珈琲", "コーヒー")]);
+        assert_eq!(pairs(&token_segments("煙草", "タバコ", options.clone())), [("煙草", "タバコ")]);
+    }
+    let options = serde_json::json!({ "ruby_mode": "mono", "jukujikun_csv": "秋桜,コスモス" });
+    assert_eq!(pairs(&token_segments("秋桜", "コスモス", options)), [("秋桜", "コスモス")]);
+}
+
+#[test]
+fn debug_lattice_is_attached_to_words_only() {
+    let options = serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ", "debug_lattice": true });
+    let tokens = tokens_for("東京　", options);
+    assert!(token(&tokens, "東京").lattice.is_some());
+    assert!(token(&tokens, "\u{3000}").lattice.is_none());
+    assert!(tokens_for("東京", serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ" }))[0].lattice.is_none());
+}
+
+#[test]
+fn debug_lattice_lists_competing_candidates() {
+    let text = "すもももももも";
+    let tokens = tokens_for(text, serde_json::json!({ "debug_lattice": true }));
+    let candidates = |token: &TokenInfo| -> Vec<(String, String)> {
+        let nodes = token.lattice.as_deref().expect("lattice");
+        assert!(nodes.iter().all(|node| text[token.byte_start..].starts_with(&node.surface)), "{}", token.surface);
+        nodes.iter().map(|node| (node.surface.clone(), node.reading.clone())).collect()
+    };
+
+    let first = candidates(&tokens[0]);
+    assert!(first.contains(&("すもも".to_string(), "スモモ".to_string())), "{:?}", first);
+    assert!(first.iter().any(|(surface, _)| surface.len() < "すもも".len()), "{:?}", first);
+
+    let later = tokens.iter().find(|token| token.byte_start > 0 && token.surface.starts_with('も')).expect("a later も token");
+    let later = candidates(later);
+    assert!(later.contains(&("も".to_string(), "モ".to_string())), "{:?}", later);
+    assert!(later.contains(&("もも".to_string(), "モモ".to_string())), "{:?}", later);
+}
+
+#[test]
+fn kana_free_compounds_get_one_group_segment() {
+    assert_eq!(pairs(&align("申込", "モウシコミ", serde_json::json!({}))), [("申込", "モウシコミ")]);
+    assert_eq!(pairs(&align("受付", "ウケツケ", serde_json::json!({}))), [("受付", "ウケツケ")]);
+}
+
+#[test]
+fn collapse_empty_segments_merges_okurigana() {
+    let options = serde_json::json!({ "collapse_empty_segments": true });
+    assert_eq!(pairs(&token_segments("食べさせる", "タベサセル", options)), [("食", "タ"), ("べさせる", "")]);
+    assert_eq!(pairs(&token_segments("食べさせる", "タベサセル", serde_json::json!({}))).len(), 5);
+}
+
+#[test]
+fn reading_with_leading_prolonged_mark() {
+    assert_eq!(pairs(&align("亜", "ーア", serde_json::json!({}))), [("亜", "ア")]);
+    assert_eq!(convert_reading("ーア", "hiragana"), "ーあ");
+}
+
+#[test]
+fn honorific_prefix_stays_bare() {
+    let options = serde_json::json!({ "honorific_prefix_bare": true });
+    assert_eq!(pairs(&token_segments("お茶", "オチャ", options.clone())), [("お", ""), ("茶", "チャ")]);
+    assert_eq!(pairs(&token_segments("ご飯", "ゴハン", options)), [("ご", ""), ("飯", "ハン")]);
+}
+
+#[test]
+fn katakana_matching_up_to_prolonged_marks_gets_no_ruby() {
+    assert_eq!(pairs(&align("コーヒー", "コーヒ", serde_json::json!({}))), [("コーヒー", "")]);
+    assert_eq!(pairs(&align("メモ", "メモ", serde_json::json!({}))), [("メモ", "")]);
+}
+
+#[test]
+fn full_reading_matches_clean_segments() {
+    let options = serde_json::json!({ "user_dict_csv": "書き込む,動詞,カキコム", "include_full_reading": true, "output_kana": "hiragana" });
+    let tokens = tokens_for("書き込む", options);
+    let verb = token(&tokens, "書き込む");
+    let joined: String = verb.ruby_segments.iter().map(|seg| if seg.ruby.is_empty() { seg.text.as_str() } else { seg.ruby.as_str() }).collect();
+    assert_eq!(verb.full_reading.as_deref(), Some(joined.as_str()));
+    assert_eq!(joined, "かきこむ");
+}
+
+#[test]
+fn mid_word_halfwidth_voicing_mark_is_composed() {
+    let text = "\u{FF83}\u{FF9E}\u{FF70}\u{FF80}\u{FF8D}\u{FF9E}\u{FF70}\u{FF7D}";
+    let tokens = tokens_for(text, serde_json::json!({ "user_dict_csv": "データベース,名詞,データベース", "normalize_halfwidth": true }));
+    let database = token(&tokens, "データベース");
+    assert_eq!((database.byte_start, database.byte_end), (0, text.len()));
+}
+
+#[test]
+fn alternatives_are_deduplicated_ordered_and_capped() {
+    let candidates = [(500, "セイ"), (300, "ナマ"), (700, "ショウ"), (200, "セイ"), (100, "*"), (300, "イ")].map(|(cost, reading)| (cost, reading.to_string()));
+    assert_eq!(rank_alternatives(candidates.clone(), "ナマ", None), ["セイ", "イ", "ショウ"]);
+    assert_eq!(rank_alternatives(candidates, "ショウ", Some(2)), ["セイ", "イ"]);
+}
+
+#[test]
+fn fullwidth_latin_stays_bare() {
+    let it = align("ＩＴ化", "アイティーカ", serde_json::json!({}));
+    assert_eq!(pairs(&it), [("Ｉ", ""), ("Ｔ", ""), ("化", "カ")]);
+    let ai = align("ＡＩ技術", "エーアイギジュツ", serde_json::json!({}));
+    assert_eq!(pairs(&ai), [("Ａ", ""), ("Ｉ", ""), ("技術", "ギジュツ")]);
+}
+
+#[test]
+fn word_ids_are_populated_and_stable() {
+    let options = serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ", "include_word_ids": true });
+    let ids = |text: &str| -> Vec<Option<(u32, bool)>> {
+        tokens_for(text, options.clone()).iter().map(|token| token.word_id.as_ref().map(|id| (id.id, id.system))).collect()
+    };
+    let first = ids("東京");
+    assert!(matches!(first[..], [Some((_, false))]), "{:?}", first);
+    assert_eq!(first, ids("東京"));
+    assert!(tokens_for("東京", serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ" }))[0].word_id.is_none());
+}
+
+#[test]
+fn symbol_reading_only_when_enabled() {
+    assert_eq!(pairs(&token_segments("〒", "*", serde_json::json!({ "symbol_readings": true }))), [("〒", "ユウビン")]);
+    assert_eq!(pairs(&token_segments("〒", "*", serde_json::json!({}))), [("〒", "")]);
+}
+
+#[test]
+fn split_points_divide_a_compound() {
+    let options = serde_json::json!({ "split_points": { "東京都": [2] } });
+    assert_eq!(pairs(&token_segments("東京都", "トウキョウト", options)), [("東京", "トウキョウ"), ("都", "ト")]);
+}
+
+#[test]
+fn symbol_tokens_pass_through() {
+    let tokens = tokens_for("・・・〜", serde_json::json!({ "user_dict_csv": "・・・,記号,*\n〜,記号,*" }));
+    let kinds: Vec<(&str, &str)> = tokens.iter().map(|token| (token.surface.as_str(), token.kind)).collect();
+    assert_eq!(kinds, [("・・・", "symbol"), ("〜", "symbol")]);
+    assert!(tokens.iter().flat_map(|token| &token.ruby_segments).all(|seg| seg.ruby.is_empty()));
+    assert_eq!(pairs(&align("ーー", "*", serde_json::json!({}))), [("ーー", "")]);
+}
+
+#[test]
+fn readings_for_words_covers_jukujikun() {
+    let input = serde_json::json!({ "words": ["今日", "勉強"], "user_dict_csv": "今日,名詞,キョウ\n勉強,名詞,ベンキョウ" });
+    let output: serde_json::Value = serde_json::from_slice(&readings_for_words(input.to_string().as_bytes())).expect("valid JSON");
+    assert_eq!(output[0]["word"], "今日");
+    assert_eq!(output[0]["reading"], "キョウ");
+    assert_eq!(output[0]["ruby_segments"], serde_json::json!([{ "text": "今日", "ruby": "キョウ" }]));
+    assert_eq!(output[1]["ruby_segments"], serde_json::json!([{ "text": "勉強", "ruby": "ベンキョウ" }]));
+}
+
+#[test]
+fn kanji_reading_on_kana_surface_is_ignored() {
+    assert_eq!(pairs(&align("すし", "寿司", serde_json::json!({}))), [("すし", "")]);
+    let tokens = tokens_for("かな", serde_json::json!({ "user_dict_csv": "かな,名詞,仮名" }));
+    assert_eq!(pairs(&token(&tokens, "かな").ruby_segments), [("かな", "")]);
+}
+
+#[test]
+fn spacing_hints_between_japanese_and_latin() {
+    let options = serde_json::json!({ "user_dict_csv": "約,接頭詞,ヤク\n10,名詞,ジュウ\nkg,名詞,キログラム", "spacing_hints": true });
+    let tokens = tokens_for("約10kg", options);
+    let hints: Vec<(&str, Option<&str>)> = tokens.iter().map(|token| (token.surface.as_str(), token.space_before)).collect();
+    assert_eq!(hints, [("約", Some("none")), ("10", Some("quarter")), ("kg", Some("none"))]);
+}
+
+#[test]
+fn context_flips_the_reading_of_itta() {
+    let options = serde_json::json!({ "user_dict_csv": "行った,動詞,イッタ\n会議,名詞,カイギ", "context_disambiguation": true });
+    let tokens = tokens_for("会議を行った", options.clone());
+    let went = token(&tokens, "行った");
+    assert_eq!(pairs(&went.ruby_segments), [("行", "オコナ"), ("っ", ""), ("た", "")]);
+    assert_eq!(went.confidence, Some(MEDIUM_CONFIDENCE));
+
+    let tokens = tokens_for("会議に行った", options);
+    assert_eq!(pairs(&token(&tokens, "行った").ruby_segments), [("行", "イ"), ("っ", ""), ("た", "")]);
+}
+
+#[test]
+fn reading_type_classifies_on_and_kun() {
+    let options = serde_json::json!({ "reading_type": true });
+    for (reading, kind) in [("オン", "on"), ("オト", "kun")] {
+        let segments = token_segments("音", reading, options.clone());
+        assert_eq!(segments[0].reading_type, Some(kind));
+    }
+}
+
+#[test]
+fn reading_shorter_than_kana_tail() {
+    assert_eq!(pairs(&align("食べる", "タ", serde_json::json!({}))), [("食", "タ"), ("べる", "")]);
+}
+
+#[test]
+fn canonical_readings_are_identical_across_widths() {
+    let ruby = |user_dict: &str| {
+        let tokens = tokens_for("東京", serde_json::json!({ "user_dict_csv": user_dict, "canonicalize_readings": true }));
+        pairs(&token(&tokens, "東京").ruby_segments).iter().map(|&(text, ruby)| (text.to_string(), ruby.to_string())).collect::<Vec<_>>()
+    };
+    assert_eq!(ruby("東京,名詞,\u{FF84}\u{FF73}\u{FF77}\u{FF6E}\u{FF73}"), ruby("東京,名詞,トウキョウ"));
+    assert_eq!(ruby("東京,名詞,とうきょう"), ruby("東京,名詞,トウキョウ"));
+}
+
+#[test]
+fn range_returns_the_window_with_absolute_offsets() {
+    let options = serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ\n学校,名詞,ガッコウ", "range": [12, 15] });
+    let tokens = tokens_for("東京。学校。", options);
+    let window: Vec<(&str, usize, usize)> = tokens.iter().map(|token| (token.surface.as_str(), token.byte_start, token.byte_end)).collect();
+    assert_eq!(window, [("学校", 9, 15), ("。", 15, 18)]);
+}
+
+#[test]
+fn single_kanji_carries_its_whole_reading() {
+    for (surface, reading) in [("木", "キ"), ("鬱", "ウツ"), ("力", "チカラ")] {
+        assert_eq!(pairs(&align(surface, reading, serde_json::json!({}))), [(surface, reading)]);
+    }
+}
+
+#[test]
+fn inline_reading_moves_into_ruby() {
+    let options = serde_json::json!({ "user_dict_csv": "漢字,名詞,カンジ\nかんじ,名詞,カンジ", "strip_inline_reading": true });
+    let tokens = tokens_for("漢字（かんじ）", options);
+    let surfaces: Vec<&str> = tokens.iter().map(|token| token.surface.as_str()).collect();
+    assert_eq!(surfaces, ["漢字"]);
+    assert_eq!(pairs(&tokens[0].ruby_segments), [("漢字", "カンジ")]);
+}
+
+#[test]
+fn target_kanji_keeps_ruby_on_targets_only() {
+    let options = serde_json::json!({ "target_kanji": ["日", "月"] });
+    assert_eq!(pairs(&token_segments("月曜日", "ゲツヨウビ", options.clone())), [("月", "ゲツ"), ("曜", ""), ("日", "ビ")]);
+    assert_eq!(pairs(&token_segments("火曜", "カヨウ", options)), [("火曜", "")]);
+}
+
+#[test]
+fn reading_side_iteration_marks_expand() {
+    assert_eq!(expand_kana_iteration_marks("スヾカ"), "スズカ");
+    assert_eq!(expand_kana_iteration_marks("コヽロ"), "ココロ");
+    assert_eq!(pairs(&align("鈴鹿", "スヾカ", serde_json::json!({}))), [("鈴鹿", "スズカ")]);
+}
+
+#[test]
+fn glossary_reading_is_hiragana_of_the_segments() {
+    let options = serde_json::json!({ "user_dict_csv": "書き込む,動詞,カキコム", "glossary_fields": true });
+    let tokens = tokens_for("書き込む", options);
+    let word = token(&tokens, "書き込む");
+    assert_eq!(word.word.as_deref(), Some("書き込む"));
+    let spelled: String = word.ruby_segments.iter().map(|seg| if seg.ruby.is_empty() { seg.text.clone() } else { seg.ruby.chars().map(kata_to_hira).collect() }).collect();
+    assert_eq!(word.reading_hira.as_deref(), Some("かきこむ"));
+    assert_eq!(word.reading_hira.as_deref(), Some(spelled.as_str()));
+}
+
+#[test]
+fn split_user_dict_compound_is_merged() {
+    let pieces = tokens_for("東京タワー", serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ\nタワー,名詞,タワー" }));
+    assert_eq!(pieces.len(), 2);
+    let compound = "東京タワー,名詞,トウキョウタワー";
+    let merged = merge_user_dict_splits(pieces, &user_dict_entries(compound), &params(serde_json::json!({})));
+    assert_eq!(merged.len(), 1);
+    assert_eq!((merged[0].byte_start, merged[0].byte_end), (0, 15));
+    assert_eq!(pairs(&merged[0].ruby_segments), [("東京タワー", "トウキョウタワー")]);
+}
+
+#[test]
+fn kanji_reading_follows_the_okurigana_spelling() {
+    assert_eq!(pairs(&align("行なう", "オコナウ", serde_json::json!({}))), [("行", "オコ"), ("な", ""), ("う", "")]);
+    assert_eq!(pairs(&align("行う", "オコナウ", serde_json::json!({}))), [("行", "オコナ"), ("う", "")]);
+}
+
+#[test]
+fn jsonl_has_one_parsable_line_per_token() {
+    let options = serde_json::json!({ "text": "東京に行く", "user_dict_csv": "東京,名詞,トウキョウ\nに,助詞,ニ\n行く,動詞,イク", "output": "jsonl" });
+    let output = analyze(options.to_string().as_bytes());
+    let text = String::from_utf8(output).expect("UTF-8");
+    assert!(text.ends_with('\n') && !text.starts_with('\u{FEFF}'));
+    let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).expect("line parses")).collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0]["surface"], "東京");
+}
+
+#[test]
+fn kanji_day_numbers_use_irregular_readings() {
+    let options = serde_json::json!({ "user_dict_csv": "日,名詞,ニチ\n一,名詞,イチ\n二,名詞,ニ\n十,名詞,ジュウ", "datetime_readings": true });
+    for (text, numeral, number_ruby, day_ruby) in [("一日", "一", "ツイ", "タチ"), ("二日", "二", "フツ", "カ"), ("二十日", "二十", "ハツ", "カ")] {
+        let tokens = tokens_for(text, options.clone());
+        assert_eq!(pairs(&token(&tokens, numeral).ruby_segments), [(numeral, number_ruby)]);
+        assert_eq!(pairs(&token(&tokens, "日").ruby_segments), [("日", day_ruby)]);
+    }
+}
+
+#[test]
+fn kanji_in_reading_is_reported() {
+    let options = serde_json::json!({ "user_dict_csv": "東京,名詞,東キョウ\n大阪,名詞,オオサカ", "reading_warnings": true });
+    let tokens = tokens_for("東京大阪", options);
+    assert!(!token(&tokens, "東京").warnings.as_ref().expect("warnings").is_empty());
+    assert!(token(&tokens, "大阪").warnings.is_none());
+}
+
+#[test]
+fn bidirectional_alignment_anchors_both_ends() {
+    let options = serde_json::json!({ "align": "bidirectional" });
+    assert_eq!(pairs(&align("お座り", "オスワリ", options.clone())), [("お", ""), ("座", "スワ"), ("り", "")]);
+    assert_eq!(pairs(&align("落ち着く", "オチツク", options)), [("落", "オ"), ("ち", ""), ("着", "ツ"), ("く", "")]);
+}
+
+#[test]
+fn meta_records_dictionary_and_mode() {
+    let output = analyze_json("東京", serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ", "include_meta": true }));
+    assert_eq!(output["meta"]["dict"], DICTIONARY_NAME);
+    assert_eq!(output["meta"]["mode"], "normal");
+    assert_eq!(output["tokens"][0]["surface"], "東京");
+
+    let other = analyze_json("東京", serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ", "include_meta": true, "ruby_mode": "mono" }));
+    assert_ne!(output["meta"]["options_hash"], other["meta"]["options_hash"]);
+}
+
+#[test]
+fn small_kana_after_kanji_is_okurigana() {
+    assert_eq!(pairs(&align("君ゃ", "キミャ", serde_json::json!({}))), [("君", "キミ"), ("ゃ", "")]);
+}
+
+#[test]
+fn coverage_counts_kanji_with_ruby() {
+    let options = serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ\n謎,名詞,*", "coverage": true });
+    let coverage = analyze_json("東京謎", options);
+    assert!((coverage.as_f64().expect("a number") - 2.0 / 3.0).abs() < 1e-9);
+}
+
+#[test]
+fn nakaguro_splits_katakana_name() {
+    let options = serde_json::json!({ "user_dict_csv": "ジョン・スミス,名詞,ジョン・スミス", "split_on_nakaguro": true });
+    let tokens = tokens_for("ジョン・スミス", options);
+    let spans: Vec<(&str, usize, usize)> = tokens.iter().map(|token| (token.surface.as_str(), token.byte_start, token.byte_end)).collect();
+    assert_eq!(spans, [("ジョン", 0, 9), ("・", 9, 12), ("スミス", 12, 21)]);
+    assert!(tokens.iter().flat_map(|token| &token.ruby_segments).all(|seg| seg.ruby.is_empty()));
+}
+
+#[test]
+fn token_without_segments_gets_its_surface() {
+    let mut tokens = vec![TokenInfo { surface: "東京".to_string(), ..Default::default() }];
+    fill_empty_segments(&mut tokens);
+    assert_eq!(pairs(&tokens[0].ruby_segments), [("東京", "")]);
+}
+
+#[test]
+fn kanji_onomatopoeia_gets_whole_word_ruby() {
+    let options = serde_json::json!({ "ruby_mode": "mono" });
+    assert_eq!(pairs(&token_segments("轟々", "ゴウゴウ", options.clone())), [("轟々", "ゴウゴウ")]);
+    assert_eq!(pairs(&token_segments("轟轟", "ゴウゴウ", options.clone())), [("轟轟", "ゴウゴウ")]);
+    let user = serde_json::json!({ "ruby_mode": "mono", "jukujikun_csv": "燦々,サンサン" });
+    assert_eq!(pairs(&token_segments("燦燦", "サンサン", user)), [("燦燦", "サンサン")]);
+}
+
+#[test]
+fn group_ruby_distributes_three_morae_over_two_kanji() {
+    let options = serde_json::json!({ "user_dict_csv": "大人,名詞,オトナ", "distribute_group_ruby": true });
+    let tokens = tokens_for("大人", options);
+    let segments = &token(&tokens, "大人").ruby_segments;
+    assert_eq!(pairs(segments), [("大人", "オトナ")]);
+    assert_eq!(segments[0].distribution.as_deref(), Some(&["オト".to_string(), "ナ".to_string()][..]));
+}
+
+#[test]
+fn reading_finds_several_surfaces() {
+    let output = surfaces_for_reading(serde_json::json!({ "reading": "かんじ" }).to_string().as_bytes());
+    let candidates: serde_json::Value = serde_json::from_slice(&output).unwrap_or_else(|_| panic!("{}", String::from_utf8_lossy(&output)));
+    let surfaces: Vec<&str> = candidates.as_array().expect("candidate array").iter().map(|c| c["surface"].as_str().unwrap()).collect();
+    assert!(surfaces.contains(&"漢字") && surfaces.contains(&"幹事"), "{:?}", surfaces);
+}
+
+#[test]
+fn redacted_readings_keep_structure() {
+    let user_dict = "東京,名詞,トウキョウ\n書き込む,動詞,カキコム";
+    let plain = tokens_for("東京に書き込む", serde_json::json!({ "user_dict_csv": user_dict }));
+    let redacted = tokens_for("東京に書き込む", serde_json::json!({ "user_dict_csv": user_dict, "redact_readings": true }));
+    assert_eq!(plain.len(), redacted.len());
+    for (plain, redacted) in plain.iter().zip(&redacted) {
+        assert_eq!(plain.surface, redacted.surface);
+        assert_eq!(plain.ruby_segments.len(), redacted.ruby_segments.len());
+        for (plain, redacted) in plain.ruby_segments.iter().zip(&redacted.ruby_segments) {
+            assert_eq!(plain.text, redacted.text);
+            assert!(redacted.ruby.chars().all(|c| c == '●'));
+        }
+    }
+    assert_eq!(pairs(&token(&redacted, "東京").ruby_segments), [("東京", "●●●●")]);
+    assert_eq!(pairs(&token(&redacted, "書き込む").ruby_segments), [("書", "●"), ("き", ""), ("込", "●"), ("む", "")]);
+}
+
+#[test]
+fn token_offsets_tile_the_text() {
+    let text = "東京 に\n行く";
+    let tokens = tokens_for(text, serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ\nに,助詞,ニ\n行く,動詞,イク" }));
+    let mut cursor = 0;
+    for token in &tokens {
+        assert_eq!(token.byte_start, cursor);
+        assert!(token.byte_end > token.byte_start);
+        assert_eq!(&text[token.byte_start..token.byte_end], token.surface);
+        cursor = token.byte_end;
+    }
+    assert_eq!(cursor, text.len());
+}
+
+#[test]
+fn mixed_script_proper_noun_annotates_kanji_only() {
+    let tokens = tokens_for("「さくらSakura櫻」", serde_json::json!({ "user_dict_csv": "さくらSakura櫻,名詞,サクラサクラサクラ" }));
+    let brand = token(&tokens, "さくらSakura櫻");
+    assert_eq!((brand.byte_start, brand.byte_end), (3, 21));
+    let annotated: Vec<(&str, &str)> = pairs(&brand.ruby_segments).into_iter().filter(|(_, ruby)| !ruby.is_empty()).collect();
+    assert_eq!(annotated, [("櫻", "サクラ")]);
+    let spelled: String = brand.ruby_segments.iter().map(|seg| seg.text.as_str()).collect();
+    assert_eq!(spelled, "さくらSakura櫻");
+}
+
+#[test]
+fn repeated_kanji_take_readings_by_position() {
+    let options = serde_json::json!({ "ruby_mode": "mono" });
+    assert_eq!(pairs(&token_segments("日曜日", "ニチヨウビ", options.clone())), [("日", "ニチ"), ("曜", "ヨウ"), ("日", "ビ")]);
+    assert_eq!(pairs(&token_segments("目的", "モクテキ", options.clone())), [("目", "モク"), ("的", "テキ")]);
+    assert_eq!(pairs(&token_segments("人人", "ヒトビト", options)), [("人", "ヒト"), ("人", "ビト")]);
+}
+
+#[test]
+fn aozora_output_marks_ruby() {
+    let input = serde_json::json!({
+        "text": "東京の日曜日に行く",
+        "user_dict_csv": "東京,名詞,トウキョウ\n日曜日,名詞,ニチヨウビ\n行く,動詞,イク",
+        "output": "aozora",
+    });
+    let output = String::from_utf8(analyze(input.to_string().as_bytes())).expect("UTF-8");
+    assert_eq!(output, "東京《トウキョウ》の日曜日《ニチヨウビ》に行《イ》く");
+
+    let input = serde_json::json!({ "text": "日曜日", "user_dict_csv": "日曜日,名詞,ニチヨウビ", "ruby_mode": "mono", "output": "aozora" });
+    let output = String::from_utf8(analyze(input.to_string().as_bytes())).expect("UTF-8");
+    assert_eq!(output, "日《ニチ》｜曜《ヨウ》｜日《ビ》");
+}
+
+/// Reads `compact` output the way `compact-ruby` in package/lib.typ does.
+fn decode_compact(data: &[u8]) -> Vec<(String, String)> {
+    let read_u32 = |at: usize| u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+    let mut records = Vec::new();
+    let mut pos = 4;
+    for _ in 0..read_u32(0) {
+        let mut field = || {
+            let len = read_u32(pos);
+            let text = String::from_utf8(data[pos + 4..pos + 4 + len].to_vec()).expect("UTF-8 field");
+            pos += 4 + len;
+            text
+        };
+        let base = field();
+        let rt = field();
+        records.push((base, rt));
+    }
+    assert_eq!(pos, data.len());
+    records
+}
+
+#[test]
+fn compact_records_round_trip() {
+    let options = serde_json::json!({ "user_dict_csv": "東京,名詞,トウキョウ\nに,助詞,ニ\n書き込む,動詞,カキコム" });
+    let tokens = tokens_for("東京に書き込む", options);
+    let expected: Vec<(String, String)> = tokens.iter().flat_map(|token| &token.ruby_segments).map(|seg| (seg.text.clone(), seg.ruby.clone())).collect();
+    let data = compact_records(&tokens);
+    assert_eq!(&data[..4], &(expected.len() as u32).to_le_bytes());
+    assert_eq!(&data[4..8], &("東京".len() as u32).to_le_bytes());
+    assert_eq!(decode_compact(&data), expected);
+}
+
+#[test]
+fn known_base_form_suppresses_conjugated_ruby() {
+    let mut features = vec!["*"; DETAIL_SCHEMA.len()];
+    features[0] = "動詞";
+    features[BASE_FORM_INDEX] = "書く";
+    features[READING_INDEX] = "カイタ";
+    let user_dict = format!("書いた,0,0,0,{}\n手紙,名詞,テガミ", features.join(","));
+    let tokens = tokens_for("手紙書いた", serde_json::json!({ "user_dict_csv": user_dict, "known_words": ["書く"] }));
+    assert!(token(&tokens, "書いた").ruby_segments.iter().all(|seg| seg.ruby.is_empty()));
+    assert_eq!(pairs(&token(&tokens, "手紙").ruby_segments), [("手紙", "テガミ")]);
+}
+
+#[test]
+fn hiragana_iteration_mark_reads_as_repeat() {
+    assert_eq!(pairs(&align("こゝろ", "ココロ", serde_json::json!({}))), [("こ", ""), ("ゝ", ""), ("ろ", "")]);
+    let tokens = tokens_for("こゝろ", serde_json::json!({ "user_dict_csv": "こゝろ,名詞,ココロ" }));
+    let kokoro = token(&tokens, "こゝろ");
+    assert_eq!((kokoro.byte_start, kokoro.byte_end), (0, 9));
+    assert!(kokoro.ruby_segments.iter().all(|seg| seg.ruby.is_empty()));
+}
+
+#[test]
+fn liaison_readings_split_at_the_moved_onset() {
+    let options = serde_json::json!({ "ruby_mode": "mono" });
+    assert_eq!(pairs(&token_segments("観音", "カンノン", options.clone())), [("観", "カン"), ("音", "ノン")]);
+    assert_eq!(pairs(&token_segments("天皇", "テンノウ", options.clone())), [("天", "テン"), ("皇", "ノウ")]);
+    assert_eq!(pairs(&token_segments("反応", "ハンノウ", options)), [("反", "ハン"), ("応", "ノウ")]);
+}
+
+#[test]
+fn grapheme_count_joins_combining_marks() {
+    let surface = "か\u{3099}き";
+    let tokens = tokens_for(surface, serde_json::json!({ "user_dict_csv": format!("{},名詞,ガキ", surface), "grapheme_count": true }));
+    let word = token(&tokens, surface);
+    assert_eq!(word.surface.chars().count(), 3);
+    assert_eq!(word.grapheme_count, Some(2));
+}
+
+#[test]
+fn kana_by_pos_picks_script_per_pos() {
+    let user_dict = [detailed_row("珈琲", "名詞", "外来語", "コーヒー"), detailed_row("紅茶", "名詞", "一般", "コウチャ")].join("\n");
+    let options = serde_json::json!({
+        "user_dict_csv": user_dict,
+        "output_kana": "hiragana",
+        "kana_by_pos": { "名詞-外来語": "katakana" },
+    });
+    let tokens = tokens_for("珈琲紅茶", options);
+    assert_eq!(pairs(&token(&tokens, "珈琲").ruby_segments), [("珈琲", "コーヒー")]);
+    assert_eq!(pairs(&token(&tokens, "紅茶").ruby_segments), [("紅茶", "こうちゃ")]);
+}
+
+#[test]
+fn halfwidth_katakana_and_punctuation_normalize() {
+    let options = serde_json::json!({ "user_dict_csv": "バス,名詞,バス\n乗る,動詞,ノル", "normalize_halfwidth": true });
+    let tokens = tokens_for("ﾊﾞｽ｢乗る｣｡", options);
+    let spans: Vec<(&str, &str, usize, usize)> = tokens.iter().map(|token| (token.surface.as_str(), token.kind, token.byte_start, token.byte_end)).collect();
+    assert_eq!(spans, [("バス", "word", 0, 9), ("「", "symbol", 9, 12), ("乗る", "word", 12, 18), ("」", "symbol", 18, 21), ("。", "symbol", 21, 24)]);
+}
+
+#[test]
+fn okurigana_marker_in_full_reading() {
+    let options = serde_json::json!({ "user_dict_csv": "食べた,動詞,タベタ\n書き込む,動詞,カキコム", "mark_okurigana": "‧" });
+    let tokens = tokens_for("食べた書き込む", options);
+    assert_eq!(token(&tokens, "食べた").full_reading.as_deref(), Some("タ‧ベタ"));
+    assert_eq!(token(&tokens, "書き込む").full_reading.as_deref(), Some("カ‧キ‧コ‧ム"));
+}
+
+#[test]
+fn lengthened_spellings_align_to_standard_readings() {
+    let options = serde_json::json!({ "tolerate_lengthening": true });
+    assert_eq!(pairs(&align("凄ーい", "スゴイ", options.clone())), [("凄", "スゴ"), ("ー", ""), ("い", "")]);
+    assert_eq!(pairs(&align("有難とお", "アリガトウ", options.clone())), [("有難", "アリガ"), ("と", ""), ("お", "")]);
+    assert_ne!(pairs(&align("凄ーい", "スゴイ", serde_json::json!({}))), pairs(&align("凄ーい", "スゴイ", options)));
+}
+
+#[test]
+fn strip_ruby_removes_paren_and_aozora_readings() {
+    let input = serde_json::json!({ "text": "漢字（かんじ）と｜東京《とうきょう》と学校《がっこう》" });
+    assert_eq!(strip_ruby(input.to_string().as_bytes()), "漢字と東京と学校".as_bytes());
+
+    let input = serde_json::json!({ "text": "漢字（かんじ）と学校《がっこう》", "styles": ["aozora"] });
+    assert_eq!(strip_ruby(input.to_string().as_bytes()), "漢字（かんじ）と学校".as_bytes());
+}
+
+#[test]
+fn loose_kana_size_matches_small_and_large_kana() {
+    let loose = serde_json::json!({ "loose_kana_size": true });
+    assert_eq!(pairs(&align("今ぢや", "イマヂャ", loose.clone())), [("今", "イマ"), ("ぢ", ""), ("や", "")]);
+    assert_ne!(pairs(&align("今ぢや", "イマヂャ", serde_json::json!({}))), [("今", "イマ"), ("ぢ", ""), ("や", "")]);
+    assert_eq!(pairs(&align("取つて", "トッテ", loose)), [("取", "ト"), ("つ", ""), ("て", "")]);
+    assert_ne!(pairs(&align("取つて", "トッテ", serde_json::json!({}))), [("取", "ト"), ("つ", ""), ("て", "")]);
+}
+
+#[test]
+fn reading_form_has_no_effect_outside_unidic() {
+    if DICTIONARY_NAME == "unidic" {
+        return;
+    }
+    let user_dict = detailed_row("書こう", "動詞", "自立", "カコウ");
+    let unset = analyze_json("書こう", serde_json::json!({ "user_dict_csv": user_dict }));
+    for form in ["surface", "canonical"] {
+        let output = analyze_json("書こう", serde_json::json!({ "user_dict_csv": user_dict, "reading_form": form }));
+        assert_eq!(output, unset, "reading_form {}", form);
+    }
+}
+
+#[test]
+fn mecab_lines_match_mecab_text_output() {
+    let rows = [detailed_row("東京", "名詞", "固有名詞", "トウキョウ"), detailed_row("に", "助詞", "格助詞", "ニ"), detailed_row("行く", "動詞", "自立", "イク")];
+    let output = analyze(serde_json::json!({ "text": "東京に 行く", "user_dict_csv": rows.join("\n"), "output": "mecab" }).to_string().as_bytes());
+    let expected: &[u8] = if DICTIONARY_NAME == "unidic" {
+        "東京\t名詞,固有名詞,*,*,*,*,トウキョウ,*,*,*,東京,*,*,*,*,*,*\n\
+         に\t助詞,格助詞,*,*,*,*,ニ,*,*,*,に,*,*,*,*,*,*\n\
+         行く\t動詞,自立,*,*,*,*,イク,*,*,*,行く,*,*,*,*,*,*\n\
+         EOS\n"
+            .as_bytes()
+    } else {
+        "東京\t名詞,固有名詞,*,*,*,*,東京,トウキョウ,*\n\
+         に\t助詞,格助詞,*,*,*,*,に,ニ,*\n\
+         行く\t動詞,自立,*,*,*,*,行く,イク,*\n\
+         EOS\n"
+            .as_bytes()
+    };
+    assert_eq!(output, expected, "{}", String::from_utf8_lossy(&output));
+}
+
+#[test]
+fn latin_to_kana_spells_acronyms() {
+    let options = serde_json::json!({ "latin_to_kana": true });
+    assert_eq!(pairs(&token_segments("NHK", "*", options.clone())), [("NHK", "エヌエイチケー")]);
+    // Mixed case is read by letter name; the hyphen is silent.
+    assert_eq!(pairs(&token_segments("Wi-Fi", "*", options.clone())), [("Wi-Fi", "ダブリューアイエフアイ")]);
+    assert_eq!(pairs(&token_segments("NHK", "*", serde_json::json!({}))), [("NHK", "")]);
+
+    assert_eq!(pairs(&token_segments("MP3", "*", options)), [("MP3", "")]);
+    let with_digits = serde_json::json!({ "latin_to_kana": true, "latin_digits_to_kana": true });
+    assert_eq!(pairs(&token_segments("MP3", "*", with_digits)), [("MP3", "エムピーサン")]);
+}
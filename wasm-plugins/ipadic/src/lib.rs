@@ -3,200 +3,143 @@ use lindera::dictionary::{load_dictionary, Dictionary, DictionaryBuilder, UserDi
 use lindera::mode::Mode;
 use lindera::segmenter::Segmenter;
 use lindera::tokenizer::Tokenizer;
+use std::collections::HashMap;
 use std::sync::OnceLock;
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+
+#[path = "../../common/align.rs"]
+mod align;
+#[path = "../../common/analyze.rs"]
+mod analyze;
+#[path = "../../common/exports.rs"]
+mod exports;
+#[path = "../../common/kana.rs"]
+mod kana;
+#[path = "../../common/output.rs"]
+mod output;
+#[path = "../../common/params.rs"]
+mod params;
+#[cfg(test)]
+#[path = "../../common/tests.rs"]
+mod tests;
+
+use align::*;
+use analyze::*;
+pub use exports::*;
+use kana::*;
+use output::*;
+use params::*;
 
 initiate_protocol!();
 
-static DICTIONARY: OnceLock<Dictionary> = OnceLock::new();
-
-fn get_dictionary() -> &'static Dictionary {
-    DICTIONARY.get_or_init(|| {
-        load_dictionary("embedded://ipadic").expect("Failed to load dictionary")
-    })
-}
-
-#[derive(Deserialize)]
-struct InputParams {
-    text: String,
-    #[serde(default)]
-    user_dict_csv: Option<String>,
-}
-
-#[derive(Serialize)]
-struct RubySegment {
-    text: String,
-    ruby: String,
-}
-
-#[derive(Serialize)]
+/// Name reported in `include_meta`.
+const DICTIONARY_NAME: &str = "ipadic";
+/// Feature column holding the reading.
+const READING_INDEX: usize = 7;
+/// Feature column holding the dictionary form.
+const BASE_FORM_INDEX: usize = 6;
+/// Feature column standing in for the surface in `surfaces_for_reading`.
+const LOOKUP_SURFACE_INDEX: usize = 6;
+
+/// Column names of the ipadic feature vector, in `details` order.
+const DETAIL_SCHEMA: [&str; 9] = [
+    "品詞",
+    "品詞細分類1",
+    "品詞細分類2",
+    "品詞細分類3",
+    "活用型",
+    "活用形",
+    "原形",
+    "読み",
+    "発音",
+];
+
+#[derive(Serialize, Default, Clone)]
 struct TokenInfo {
     surface: String,
-    details: Vec<String>, 
+    details: Details, 
     ruby_segments: Vec<RubySegment>, 
+    /// Set when the reading came from a heuristic rather than the dictionary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confidence: Option<f32>,
+    /// "surface reading" index key for kanji-bearing tokens (`search_keys`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_key: Option<String>,
+    /// Glossary headword and its hiragana reading (`glossary_fields`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reading_hira: Option<String>,
+    /// Data problems found in this token's dictionary entry (`reading_warnings`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warnings: Option<Vec<String>>,
+    /// Dictionary candidates starting at this token's offset (`debug_lattice`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lattice: Option<Vec<LatticeNode>>,
+    /// Complete reading before it was split into segments (`include_full_reading`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    full_reading: Option<String>,
+    /// Dictionary entry the token came from (`include_word_ids`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word_id: Option<EntryId>,
+    /// Length of the surface in grapheme clusters (`grapheme_count`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grapheme_count: Option<usize>,
+    /// Recommended space before this token (`spacing_hints`): "quarter" or "none".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    space_before: Option<&'static str>,
+    /// Other dictionary readings for the surface (`include_alternatives`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alternatives: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// "word", "symbol", "whitespace" or "ideographic_space" (U+3000).
+    kind: &'static str,
+    byte_start: usize,
+    byte_end: usize,
+    /// Surface for token-level `#ruby(base, rt)` (`token_level_ruby`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base: Option<String>,
+    /// Whole-token reading, empty when no segment carries ruby.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rt: Option<String>,
 }
 
-fn hira_to_kata(c: char) -> char {
-    if c >= '\u{3041}' && c <= '\u{3096}' {
-        std::char::from_u32(c as u32 + 0x60).unwrap()
-    } else {
-        c
-    }
-}
-
-fn build_ruby_segments(surface: &str, reading: &str) -> Vec<RubySegment> {
-    if reading == "*" || surface == reading {
-        return vec![RubySegment {
-            text: surface.to_string(),
-            ruby: "".to_string(),
-        }];
+impl DictionaryToken for TokenInfo {
+    fn from_entry(surface: &str, details: Vec<String>, _params: &InputParams) -> (Self, String) {
+        let reading = details.get(READING_INDEX).map_or("*", String::as_str).to_string();
+        let token = TokenInfo {
+            surface: surface.to_string(),
+            details: Details::List(details),
+            ..Default::default()
+        };
+        (token, reading)
     }
 
-    let sur_chars: Vec<char> = surface.chars().collect();
-    let read_chars: Vec<char> = reading.chars().collect();
-    
-    let mut segments = Vec::new();
-    let mut buffer_s = String::new();
-    let mut r_idx = 0;
-
-    for &s_char in &sur_chars {
-        let s_kata = hira_to_kata(s_char);
-        let is_hiragana = s_char != s_kata;
-
-        if is_hiragana {
-            if r_idx < read_chars.len() {
-                let remaining_reading = &read_chars[r_idx..];
-
-                if let Some(pos_in_remaining) = remaining_reading.iter().position(|&c| c == s_kata) {
-                    let kanji_reading_len = pos_in_remaining;
-                    
-                    if !buffer_s.is_empty() {
-                        let end_idx = r_idx + kanji_reading_len;
-                        if end_idx <= read_chars.len() {
-                            let kanji_reading: String = read_chars[r_idx..end_idx].iter().collect();
-                            segments.push(RubySegment {
-                                text: buffer_s.clone(),
-                                ruby: kanji_reading,
-                            });
-                        }
-                        buffer_s.clear();
-                    }
-
-                    segments.push(RubySegment {
-                        text: s_char.to_string(),
-                        ruby: "".to_string(),
-                    });
-
-                    r_idx += kanji_reading_len + 1;
-                    continue;
-                }
-            }
+    fn whitespace(_text: &str) -> Self {
+        let mut gap_details = vec!["*".to_string(); DETAIL_SCHEMA.len()];
+        gap_details[0] = "Whitespace".to_string();
+        TokenInfo {
+            details: Details::List(gap_details),
+            ..Default::default()
         }
-        
-        buffer_s.push(s_char);
     }
 
-    if !buffer_s.is_empty() {
-        let remaining_ruby: String = if r_idx < read_chars.len() {
-            read_chars[r_idx..].iter().collect()
-        } else {
-            "".to_string()
-        };
-        segments.push(RubySegment {
-            text: buffer_s,
-            ruby: remaining_ruby,
-        });
+    fn pos(&self) -> (&str, &str) {
+        let features = self.details.features();
+        (features.first().copied().unwrap_or("*"), features.get(1).copied().unwrap_or("*"))
     }
 
-    segments
-}
-
-#[wasm_func]
-pub fn analyze(input_bytes: &[u8]) -> Vec<u8> {
-    let params: InputParams = match serde_json::from_slice(input_bytes) {
-        Ok(p) => p,
-        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
-    };
-
-    let dictionary = get_dictionary().clone();
-
-    let user_dictionary = if let Some(csv_data) = params.user_dict_csv {
-        let builder = DictionaryBuilder::new(dictionary.metadata.clone());
-        match UserDictionaryLoader::load_from_csv_data(builder, csv_data.as_bytes()) {
-            Ok(ud) => Some(ud),
-            Err(e) => return format!("Error: Failed to build user dictionary: {}", e).into_bytes(),
-        }
-    } else {
-        None
-    };
-
-    let segmenter = Segmenter::new(Mode::Normal, dictionary, user_dictionary);
-    let tokenizer = Tokenizer::new(segmenter);
-
-    let mut tokens = match tokenizer.tokenize(&params.text) {
-        Ok(t) => t,
-        Err(e) => return format!("Error: Tokenization failed: {}", e).into_bytes(),
-    };
-
-    let mut result_list: Vec<TokenInfo> = Vec::new();
-    let mut cursor_byte = 0;
-    let text_bytes = params.text.as_bytes();
-
-    let dummy_details = vec!["*".to_string(); 9];
-
-    for token in tokens.iter_mut() {
-        if token.byte_start > cursor_byte {
-            let gap_slice = &text_bytes[cursor_byte..token.byte_start];
-            let gap_text = String::from_utf8_lossy(gap_slice).to_string();
-            
-            let mut gap_details = dummy_details.clone();
-            gap_details[0] = "Whitespace".to_string();
-
-            result_list.push(TokenInfo {
-                surface: gap_text.clone(),
-                details: gap_details,
-                ruby_segments: vec![RubySegment {
-                    text: gap_text,
-                    ruby: "".to_string(),
-                }],
-            });
-        }
-
-        let surface = token.surface.to_string();
-        let details_vec: Vec<String> = token.details().iter().map(|s| s.to_string()).collect();
-    
-        let reading = details_vec.get(7).map(|s| s.as_str()).unwrap_or("*");
-
-        let ruby_segments = build_ruby_segments(&surface, reading);
-
-        result_list.push(TokenInfo {
-            surface,
-            details: details_vec,
-            ruby_segments,
-        });
-
-        cursor_byte = token.byte_end;
+    fn base_form(&self) -> Option<&str> {
+        self.details.features().get(BASE_FORM_INDEX).copied()
     }
 
-    if cursor_byte < text_bytes.len() {
-        let gap_slice = &text_bytes[cursor_byte..];
-        let gap_text = String::from_utf8_lossy(gap_slice).to_string();
-        
-        let mut gap_details = dummy_details.clone();
-        gap_details[0] = "Whitespace".to_string();
-
-        result_list.push(TokenInfo {
-            surface: gap_text.clone(),
-            details: gap_details,
-            ruby_segments: vec![RubySegment {
-                text: gap_text,
-                ruby: "".to_string(),
-            }],
-        });
+    fn features(&self) -> Vec<&str> {
+        self.details.features()
     }
 
-    match serde_json::to_vec(&result_list) {
-        Ok(bytes) => bytes,
-        Err(e) => format!("Error: Serialization failed: {}", e).into_bytes(),
+    fn details_as_map(&mut self) {
+        self.details = std::mem::take(&mut self.details).into_map();
     }
-}
\ No newline at end of file
+}
@@ -3,62 +3,180 @@ use lindera::dictionary::{load_dictionary, Dictionary, DictionaryBuilder, UserDi
 use lindera::mode::Mode;
 use lindera::segmenter::Segmenter;
 use lindera::tokenizer::Tokenizer;
+use std::collections::HashMap;
 use std::sync::OnceLock;
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+
+#[path = "../../common/align.rs"]
+mod align;
+#[path = "../../common/analyze.rs"]
+mod analyze;
+#[path = "../../common/exports.rs"]
+mod exports;
+#[path = "../../common/kana.rs"]
+mod kana;
+#[path = "../../common/output.rs"]
+mod output;
+#[path = "../../common/params.rs"]
+mod params;
+#[cfg(test)]
+#[path = "../../common/tests.rs"]
+mod tests;
+
+use align::*;
+use analyze::*;
+pub use exports::*;
+use kana::*;
+use output::*;
+use params::*;
 
 initiate_protocol!();
 
-static DICTIONARY: OnceLock<Dictionary> = OnceLock::new();
-
-fn get_dictionary() -> &'static Dictionary {
-    DICTIONARY.get_or_init(|| {
-        load_dictionary("embedded://unidic").expect("Failed to load dictionary")
-    })
-}
-
-#[derive(Deserialize)]
-struct InputParams {
-    text: String,
-    #[serde(default)]
-    user_dict_csv: Option<String>,
-}
-
-#[derive(Serialize)]
-struct RubySegment {
-    text: String,
-    ruby: String,
-}
-
-#[derive(Serialize)]
+/// Name reported in `include_meta`.
+const DICTIONARY_NAME: &str = "unidic";
+/// Feature column holding the reading.
+const READING_INDEX: usize = 6;
+/// Feature column holding the dictionary form.
+const BASE_FORM_INDEX: usize = 10;
+/// Feature column standing in for the surface in `surfaces_for_reading`
+/// (書字形出現形, the orthographic form as written).
+const LOOKUP_SURFACE_INDEX: usize = 8;
+
+/// Column names of the unidic feature vector, in `details` order.
+const DETAIL_SCHEMA: [&str; 17] = [
+    "品詞大分類",
+    "品詞中分類",
+    "品詞小分類",
+    "品詞細分類",
+    "活用型",
+    "活用形",
+    "語彙素読み",
+    "語彙素",
+    "書字形出現形",
+    "発音形出現形",
+    "書字形基本形",
+    "発音形基本形",
+    "語種",
+    "語頭変化型",
+    "語頭変化形",
+    "語末変化型",
+    "語末変化形",
+];
+
+#[derive(Serialize, Default, Clone)]
 struct TokenInfo {
     surface: String,
-    details: Vec<String>, 
+    details: Details, 
     ruby_segments: Vec<RubySegment>, 
+    /// Set when the reading came from a heuristic rather than the dictionary.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confidence: Option<f32>,
+    /// "surface reading" index key for kanji-bearing tokens (`search_keys`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_key: Option<String>,
+    /// Glossary headword and its hiragana reading (`glossary_fields`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reading_hira: Option<String>,
+    /// Data problems found in this token's dictionary entry (`reading_warnings`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    warnings: Option<Vec<String>>,
+    /// Dictionary candidates starting at this token's offset (`debug_lattice`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lattice: Option<Vec<LatticeNode>>,
+    /// Complete reading before it was split into segments (`include_full_reading`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    full_reading: Option<String>,
+    /// Dictionary entry the token came from (`include_word_ids`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    word_id: Option<EntryId>,
+    /// Length of the surface in grapheme clusters (`grapheme_count`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grapheme_count: Option<usize>,
+    /// Recommended space before this token (`spacing_hints`): "quarter" or "none".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    space_before: Option<&'static str>,
+    /// Other dictionary readings for the surface (`include_alternatives`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alternatives: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    /// "word", "symbol", "whitespace" or "ideographic_space" (U+3000).
+    kind: &'static str,
+    byte_start: usize,
+    byte_end: usize,
+    /// Surface for token-level `#ruby(base, rt)` (`token_level_ruby`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base: Option<String>,
+    /// Whole-token reading, empty when no segment carries ruby.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explain: Option<Vec<String>>,
+    /// Pitch accent of the reading (`pitch_notation`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pitch: Option<String>,
+    reading_src: ReadingSource,
 }
 
-fn hira_to_kata(c: char) -> char {
-    if c >= '\u{3041}' && c <= '\u{3096}' {
-        std::char::from_u32(c as u32 + 0x60).unwrap()
-    } else {
-        c
-    }
-}
-
-fn is_hiragana(c: char) -> bool {
-    c >= '\u{3040}' && c <= '\u{309F}'
+/// Where a token's reading came from, serialized as a small integer code.
+#[derive(Clone, Copy, Default)]
+enum ReadingSource {
+    /// No reading was looked up (kana-only token or `*` in the dictionary).
+    #[default]
+    None = 0,
+    /// Index 6 (lemma reading), used as is.
+    Lemma = 1,
+    /// Index 9 (phonological surface), reconstructed to standard orthography.
+    Reconstructed = 2,
+    /// Index 6, after the index 9 reconstruction was rejected as implausible.
+    LemmaFallback = 3,
+    /// Replaced by a heuristic table (place names, dates).
+    Heuristic = 4,
+    /// Index 6 for a conjugated word, chosen by `reading_form: canonical`.
+    Canonical = 5,
 }
 
-fn is_kanji(c: char) -> bool {
-    (c >= '\u{4E00}' && c <= '\u{9FFF}') ||
-    (c >= '\u{3400}' && c <= '\u{4DBF}') ||
-    (c >= '\u{20000}' && c <= '\u{2A6DF}')
+impl Serialize for ReadingSource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
 }
 
-fn contains_kanji(s: &str) -> bool {
-    s.chars().any(is_kanji)
+/// Column of the accent type (`aType`) in the full UniDic feature layout.
+/// The 17-column build embedded in this plugin stops before it.
+const ACCENT_TYPE_INDEX: usize = 24;
+
+/// Renders a reading's pitch accent. `accent` is the UniDic `aType`, the
+/// mora after which the pitch drops (0 = heiban, no drop); when several
+/// types are listed the first is used. "numeric" gives the type itself,
+/// "mark" the reading with ＼ after the accented mora (ハ＼シ for type 1).
+fn pitch_notation(reading: &str, accent: &str, notation: &str) -> Option<String> {
+    let accent_type: usize = accent.split(',').next()?.trim().parse().ok()?;
+    match notation {
+        "numeric" => Some(accent_type.to_string()),
+        "mark" => {
+            let morae = mora_units(reading);
+            if accent_type > morae.len() {
+                return None;
+            }
+            let mut marked = String::new();
+            for (i, mora) in morae.iter().enumerate() {
+                marked.push_str(mora);
+                if i + 1 == accent_type {
+                    marked.push('＼');
+                }
+            }
+            Some(marked)
+        }
+        _ => None,
+    }
 }
 
 /// Reconstructs the orthographic reading from Surface and Phonetic Reading.
+/// Used for conjugated words, so trailing kana follow the written form.
 fn reconstruct_orthography(surface: &str, phonetic: &str) -> String {
     let s_chars: Vec<char> = surface.chars().collect();
     let p_chars: Vec<char> = phonetic.chars().collect();
@@ -98,131 +216,20 @@ fn reconstruct_orthography(surface: &str, phonetic: &str) -> String {
     format!("{}{}", head_phonetic, tail_orthography)
 }
 
-fn build_ruby_segments(surface: &str, reading: &str) -> Vec<RubySegment> {
-    if reading == "*" || surface == reading {
-        return vec![RubySegment {
-            text: surface.to_string(),
-            ruby: "".to_string(),
-        }];
-    }
-
-    let sur_chars: Vec<char> = surface.chars().collect();
-    let read_chars: Vec<char> = reading.chars().collect();
-    
-    let mut segments = Vec::new();
-    let mut buffer_s = String::new();
-    let mut r_idx = 0;
-
-    for &s_char in &sur_chars {
-        let s_kata = hira_to_kata(s_char);
-        let is_hiragana = s_char != s_kata;
-
-        if is_hiragana {
-            if r_idx < read_chars.len() {
-                let remaining_reading = &read_chars[r_idx..];
-
-                if let Some(pos_in_remaining) = remaining_reading.iter().position(|&c| c == s_kata) {
-                    let kanji_reading_len = pos_in_remaining;
-                    
-                    if !buffer_s.is_empty() {
-                        let end_idx = r_idx + kanji_reading_len;
-                        if end_idx <= read_chars.len() {
-                            let kanji_reading: String = read_chars[r_idx..end_idx].iter().collect();
-                            segments.push(RubySegment {
-                                text: buffer_s.clone(),
-                                ruby: kanji_reading,
-                            });
-                        }
-                        buffer_s.clear();
-                    }
-
-                    segments.push(RubySegment {
-                        text: s_char.to_string(),
-                        ruby: "".to_string(),
-                    });
-
-                    r_idx += kanji_reading_len + 1;
-                    continue;
-                }
-            }
-        }
-        
-        buffer_s.push(s_char);
-    }
-
-    if !buffer_s.is_empty() {
-        let remaining_ruby: String = if r_idx < read_chars.len() {
-            read_chars[r_idx..].iter().collect()
-        } else {
-            "".to_string()
-        };
-        segments.push(RubySegment {
-            text: buffer_s,
-            ruby: remaining_ruby,
-        });
-    }
-
-    segments
-}
-
-#[wasm_func]
-pub fn analyze(input_bytes: &[u8]) -> Vec<u8> {
-    let params: InputParams = match serde_json::from_slice(input_bytes) {
-        Ok(p) => p,
-        Err(e) => return format!("Error: Invalid JSON: {}", e).into_bytes(),
-    };
-
-    let dictionary = get_dictionary().clone();
-
-    let user_dictionary = if let Some(csv_data) = params.user_dict_csv {
-        let builder = DictionaryBuilder::new(dictionary.metadata.clone());
-        match UserDictionaryLoader::load_from_csv_data(builder, csv_data.as_bytes()) {
-            Ok(ud) => Some(ud),
-            Err(e) => return format!("Error: Failed to build user dictionary: {}", e).into_bytes(),
-        }
-    } else {
-        None
-    };
-
-    let segmenter = Segmenter::new(Mode::Normal, dictionary, user_dictionary);
-    let tokenizer = Tokenizer::new(segmenter);
-
-    let mut tokens = match tokenizer.tokenize(&params.text) {
-        Ok(t) => t,
-        Err(e) => return format!("Error: Tokenization failed: {}", e).into_bytes(),
-    };
-
-    let mut result_list: Vec<TokenInfo> = Vec::new();
-    let mut cursor_byte = 0;
-    let text_bytes = params.text.as_bytes();
-    let dummy_details = vec!["*".to_string(); 17];
-
-    for token in tokens.iter_mut() {
-        if token.byte_start > cursor_byte {
-            let gap_slice = &text_bytes[cursor_byte..token.byte_start];
-            let gap_text = String::from_utf8_lossy(gap_slice).to_string();
-            let mut gap_details = dummy_details.clone();
-            gap_details[0] = "Whitespace".to_string();
-            result_list.push(TokenInfo {
-                surface: gap_text.clone(),
-                details: gap_details,
-                ruby_segments: vec![RubySegment { text: gap_text, ruby: "".to_string() }],
-            });
-        }
-
-        let surface = token.surface.to_string();
-        let details_vec: Vec<String> = token.details().iter().map(|s| s.to_string()).collect();
+impl DictionaryToken for TokenInfo {
+    fn from_entry(surface: &str, details: Vec<String>, params: &InputParams) -> (Self, String) {
+        // 1. Safety Filter: No Kanji -> No Ruby (unless kana annotation is requested)
+        let mut explain_notes: Vec<String> = Vec::new();
 
-        // 1. Safety Filter: No Kanji -> No Ruby
-        let ruby_segments = if !contains_kanji(&surface) {
-            vec![RubySegment {
-                text: surface.clone(),
-                ruby: "".to_string(),
-            }]
+        let (final_reading, reading_src) = if !contains_kanji(surface) && !annotates_kana(surface, params) {
+            ("*".to_string(), ReadingSource::None)
         } else {
             // 2. Logic Split: Conjugated vs Non-Conjugated
-            let conjugation_type = details_vec.get(4).map(|s| s.as_str()).unwrap_or("*");
-            let is_conjugated = conjugation_type != "*";
+            let conjugation_type = details.get(4).map(|s| s.as_str()).unwrap_or("*");
+            // `reading_form: canonical` keeps the raw lemma reading, like
+            // `disable_conjugation_heuristic`.
+            let canonical = conjugation_type != "*" && params.reading_form.as_deref() == Some("canonical");
+            let is_conjugated = conjugation_type != "*" && !params.disable_conjugation_heuristic && !canonical;
 
             let (source_idx, needs_reconstruction) = if is_conjugated {
                 // Case: Verbs/Adjectives
@@ -232,51 +239,211 @@ pub fn analyze(input_bytes: &[u8]) -> Vec<u8> {
             } else {
                 // Case: Nouns/Particles
                 // Use Index 6 (Lemma Reading) to preserve standard orthography.
-                (6, false)
+                (READING_INDEX, false)
             };
 
-            let raw_reading = details_vec.get(source_idx)
+            let raw_reading = details.get(source_idx)
                 .filter(|s| s.as_str() != "*")
                 .map(|s| s.as_str())
                 // Fallback to Index 6 if intended source is unavailable
-                .or_else(|| details_vec.get(6).map(|s| s.as_str()))
+                .or_else(|| details.get(READING_INDEX).map(|s| s.as_str()))
                 .unwrap_or("*");
 
             // 3. Apply Reconstruction if flagged
-            let final_reading = if raw_reading == "*" {
-                "*".to_string()
+            if raw_reading == "*" {
+                ("*".to_string(), ReadingSource::None)
             } else if needs_reconstruction {
-                reconstruct_orthography(&surface, raw_reading)
+                let reconstructed = reconstruct_orthography(surface, raw_reading);
+                let lemma_reading = details.get(READING_INDEX).map(|s| s.as_str()).unwrap_or("*");
+                if is_plausible_reading(surface, &reconstructed) || lemma_reading == "*" {
+                    (reconstructed, ReadingSource::Reconstructed)
+                } else {
+                    explain_notes.push(format!(
+                        "reconstructed reading {} is implausible for {}; fell back to index 6 ({})",
+                        reconstructed, surface, lemma_reading
+                    ));
+                    (lemma_reading.to_string(), ReadingSource::LemmaFallback)
+                }
+            } else if canonical {
+                (raw_reading.to_string(), ReadingSource::Canonical)
             } else {
-                raw_reading.to_string()
-            };
+                (raw_reading.to_string(), ReadingSource::Lemma)
+            }
+        };
 
-            build_ruby_segments(&surface, &final_reading)
+        let pitch = details
+            .get(ACCENT_TYPE_INDEX)
+            .filter(|_| params.pitch_notation != "none" && final_reading != "*")
+            .and_then(|accent| pitch_notation(&final_reading, accent, &params.pitch_notation))
+            .map(|pitch| convert_reading(&pitch, &params.output_kana));
+
+        let token = TokenInfo {
+            surface: surface.to_string(),
+            details: Details::List(details),
+            explain: if params.explain { Some(explain_notes) } else { None },
+            pitch,
+            reading_src,
+            ..Default::default()
         };
+        (token, final_reading)
+    }
+
+    fn whitespace(_text: &str) -> Self {
+        let mut gap_details = vec!["*".to_string(); DETAIL_SCHEMA.len()];
+        gap_details[0] = "Whitespace".to_string();
+        TokenInfo {
+            details: Details::List(gap_details),
+            ..Default::default()
+        }
+    }
 
-        result_list.push(TokenInfo {
-            surface,
-            details: details_vec,
-            ruby_segments,
-        });
+    fn pos(&self) -> (&str, &str) {
+        let features = self.details.features();
+        (features.first().copied().unwrap_or("*"), features.get(1).copied().unwrap_or("*"))
+    }
 
-        cursor_byte = token.byte_end;
+    fn base_form(&self) -> Option<&str> {
+        self.details.features().get(BASE_FORM_INDEX).copied()
     }
 
-    if cursor_byte < text_bytes.len() {
-        let gap_slice = &text_bytes[cursor_byte..];
-        let gap_text = String::from_utf8_lossy(gap_slice).to_string();
-        let mut gap_details = dummy_details.clone();
-        gap_details[0] = "Whitespace".to_string();
-        result_list.push(TokenInfo {
-            surface: gap_text.clone(),
-            details: gap_details,
-            ruby_segments: vec![RubySegment { text: gap_text, ruby: "".to_string() }],
-        });
+    fn features(&self) -> Vec<&str> {
+        self.details.features()
     }
 
-    match serde_json::to_vec(&result_list) {
-        Ok(bytes) => bytes,
-        Err(e) => format!("Error: Serialization failed: {}", e).into_bytes(),
+    fn heuristic_reading(&mut self, _reading: &str) {
+        self.reading_src = ReadingSource::Heuristic;
+        // The accent belongs to the dictionary reading that was replaced.
+        self.pitch = None;
+    }
+
+    fn details_as_map(&mut self) {
+        self.details = std::mem::take(&mut self.details).into_map();
+    }
+}
+
+/// Rejects readings that cannot belong to the surface: at most five kana per
+/// kanji (承 -> うけたまわ) plus one per remaining character.
+fn is_plausible_reading(surface: &str, reading: &str) -> bool {
+    let kanji_count = surface.chars().filter(|&c| is_kanji(c)).count();
+    let other_count = surface.chars().count() - kanji_count;
+    reading.chars().count() <= kanji_count * 5 + other_count
+}
+
+#[cfg(test)]
+mod unidic_tests {
+    use super::*;
+
+    fn params(options: serde_json::Value) -> InputParams {
+        let mut input = serde_json::json!({ "text": "" });
+        if let (Some(map), Some(options)) = (input.as_object_mut(), options.as_object()) {
+            map.extend(options.clone());
+        }
+        serde_json::from_value(input).expect("valid options")
+    }
+
+    /// Builds a token from a UniDic feature row with only `features` filled in.
+    fn entry(surface: &str, features: &[(usize, &str)], options: serde_json::Value) -> (TokenInfo, String) {
+        let mut details = vec!["*".to_string(); DETAIL_SCHEMA.len()];
+        for &(idx, value) in features {
+            details[idx] = value.to_string();
+        }
+        TokenInfo::from_entry(surface, details, &params(options))
     }
-}
\ No newline at end of file
+
+    fn segment_pairs(surface: &str, reading: &str, options: serde_json::Value) -> Vec<(String, String)> {
+        token_ruby_segments(surface, reading, 0, &params(options)).into_iter().map(|seg| (seg.text, seg.ruby)).collect()
+    }
+
+    fn source_code(token: &TokenInfo) -> serde_json::Value {
+        serde_json::to_value(token.reading_src).expect("serializable")
+    }
+
+    #[test]
+    fn disable_conjugation_heuristic_reads_the_lemma() {
+        let features = [(0, "動詞"), (4, "サ行変格"), (5, "連用形-一般"), (6, "カイセツスル"), (9, "カイセツシ"), (10, "解説する")];
+        let (token, reading) = entry("解説し", &features, serde_json::json!({}));
+        assert_eq!((reading.as_str(), source_code(&token)), ("カイセツシ", serde_json::json!(2)));
+
+        let (token, reading) = entry("解説し", &features, serde_json::json!({ "disable_conjugation_heuristic": true }));
+        assert_eq!((reading.as_str(), source_code(&token)), ("カイセツスル", serde_json::json!(1)));
+    }
+
+    #[test]
+    fn implausible_reconstruction_falls_back_to_the_lemma() {
+        assert!(is_plausible_reading("承る", "ウケタマワル"));
+        assert!(!is_plausible_reading("行っ", "イッテイッテイッタ"));
+
+        let features = [(0, "動詞"), (4, "五段-カ行"), (5, "連用形-促音便"), (6, "イク"), (9, "イッテイッテイッタ")];
+        let (token, reading) = entry("行っ", &features, serde_json::json!({ "explain": true }));
+        assert_eq!((reading.as_str(), source_code(&token)), ("イク", serde_json::json!(3)));
+        let notes = token.explain.expect("explain notes");
+        assert!(notes.iter().any(|note| note.contains("implausible") && note.contains("行っ")), "{:?}", notes);
+    }
+
+    #[test]
+    fn reading_source_codes() {
+        let codes: Vec<serde_json::Value> = [
+            ReadingSource::None,
+            ReadingSource::Lemma,
+            ReadingSource::Reconstructed,
+            ReadingSource::LemmaFallback,
+            ReadingSource::Heuristic,
+            ReadingSource::Canonical,
+        ]
+        .iter()
+        .map(|source| serde_json::to_value(source).expect("serializable"))
+        .collect();
+        assert_eq!(codes, serde_json::json!([0, 1, 2, 3, 4, 5]).as_array().unwrap().clone());
+    }
+
+    #[test]
+    fn reading_form_canonical_keeps_the_lemma_reading() {
+        let features = [(0, "動詞"), (4, "五段-カ行"), (5, "意志推量形"), (6, "カク"), (9, "カコー")];
+        let (token, reading) = entry("書こう", &features, serde_json::json!({}));
+        assert_eq!((reading.as_str(), source_code(&token)), ("カコウ", serde_json::json!(2)));
+        assert_eq!(segment_pairs("書こう", &reading, serde_json::json!({})), [("書".to_string(), "カ".to_string()), ("こ".to_string(), "".to_string()), ("う".to_string(), "".to_string())]);
+
+        let canonical = serde_json::json!({ "reading_form": "canonical" });
+        let (token, reading) = entry("書こう", &features, canonical.clone());
+        assert_eq!((reading.as_str(), source_code(&token)), ("カク", serde_json::json!(5)));
+        assert_eq!(segment_pairs("書こう", &reading, canonical), [("書".to_string(), "カ".to_string()), ("こう".to_string(), "".to_string())]);
+    }
+
+    /// A noun row in the full UniDic layout, with `aType` filled in.
+    fn accented_entry(surface: &str, reading: &str, accent: &str, options: serde_json::Value) -> TokenInfo {
+        let mut details = vec!["*".to_string(); ACCENT_TYPE_INDEX + 1];
+        details[0] = "名詞".to_string();
+        details[READING_INDEX] = reading.to_string();
+        details[ACCENT_TYPE_INDEX] = accent.to_string();
+        TokenInfo::from_entry(surface, details, &params(options)).0
+    }
+
+    #[test]
+    fn pitch_notation_numeric_and_mark() {
+        let numeric = serde_json::json!({ "pitch_notation": "numeric" });
+        assert_eq!(accented_entry("箸", "ハシ", "1", numeric.clone()).pitch.as_deref(), Some("1"));
+        assert_eq!(accented_entry("橋", "ハシ", "2", numeric.clone()).pitch.as_deref(), Some("2"));
+        assert_eq!(accented_entry("端", "ハシ", "0", numeric).pitch.as_deref(), Some("0"));
+
+        let mark = serde_json::json!({ "pitch_notation": "mark" });
+        assert_eq!(accented_entry("箸", "ハシ", "1", mark.clone()).pitch.as_deref(), Some("ハ＼シ"));
+        assert_eq!(accented_entry("橋", "ハシ", "2", mark.clone()).pitch.as_deref(), Some("ハシ＼"));
+        assert_eq!(accented_entry("端", "ハシ", "0", mark.clone()).pitch.as_deref(), Some("ハシ"));
+        // Morae, not characters: キョ is one mora. The first listed type wins.
+        assert_eq!(accented_entry("今日", "キョウ", "1,0", mark).pitch.as_deref(), Some("キョ＼ウ"));
+
+        let hiragana = serde_json::json!({ "pitch_notation": "mark", "output_kana": "hiragana" });
+        assert_eq!(accented_entry("箸", "ハシ", "1", hiragana).pitch.as_deref(), Some("は＼し"));
+    }
+
+    #[test]
+    fn pitch_notation_needs_the_option_and_the_accent_column() {
+        assert_eq!(accented_entry("箸", "ハシ", "1", serde_json::json!({})).pitch, None);
+        let (token, _) = entry("箸", &[(0, "名詞"), (READING_INDEX, "ハシ")], serde_json::json!({ "pitch_notation": "mark" }));
+        assert_eq!(token.pitch, None);
+
+        let mut input = params(serde_json::json!({ "pitch_notation": "high-low" }));
+        input.text = "箸".to_string();
+        assert_eq!(validate_params(&input).err().as_deref(), Some("Error: pitch_notation must be one of: none, numeric, mark"));
+    }
+}